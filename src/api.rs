@@ -1,14 +1,81 @@
+use std::fmt;
+use std::time::Duration;
+
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use futures_util::StreamExt;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::sync::mpsc;
 
-use crate::conversation::{Message, Role};
+use crate::config::{Config, RetryConfig};
+use crate::conversation::{Message, Role, ThinkingBlock, ToolCall, Usage};
 
 #[derive(Serialize)]
 struct ApiMessage {
     role: String,
-    content: String,
+    content: ApiContent,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ApiContent {
+    Text(String),
+    Blocks(Vec<ApiContentBlock>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ApiContentBlock {
+    Text {
+        text: String,
+    },
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct ImageSource {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    media_type: String,
+    data: String,
+}
+
+/// A tool offered to the model.
+#[derive(Clone, Serialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+}
+
+#[derive(Serialize)]
+struct ThinkingConfig {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    budget_tokens: u32,
 }
 
 #[derive(Serialize)]
@@ -17,21 +84,50 @@ struct ApiRequest {
     max_tokens: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<ThinkingConfig>,
     messages: Vec<ApiMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDefinition>,
     #[serde(skip_serializing_if = "std::ops::Not::not")]
     stream: bool,
 }
 
+#[derive(Serialize)]
+struct CountTokensRequest {
+    model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<ApiMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDefinition>,
+}
+
+#[derive(Deserialize)]
+struct CountTokensResponse {
+    input_tokens: u64,
+}
+
 #[derive(Deserialize)]
 struct ContentBlock {
     #[serde(rename = "type")]
-    _type: String,
+    block_type: String,
     text: Option<String>,
+    thinking: Option<String>,
+    signature: Option<String>,
+    data: Option<String>,
+    id: Option<String>,
+    name: Option<String>,
+    input: Option<Value>,
 }
 
 #[derive(Deserialize)]
 struct ApiResponse {
     content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<ApiUsage>,
 }
 
 #[derive(Deserialize)]
@@ -39,6 +135,12 @@ struct StreamDelta {
     #[serde(rename = "type")]
     delta_type: Option<String>,
     text: Option<String>,
+    thinking: Option<String>,
+    signature: Option<String>,
+    /// A piece of a tool call's input JSON.
+    partial_json: Option<String>,
+    /// Sent with `message_delta`.
+    stop_reason: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -46,36 +148,255 @@ struct StreamEvent {
     #[serde(rename = "type")]
     event_type: String,
     delta: Option<StreamDelta>,
+    error: Option<ErrorDetail>,
+    /// Sent with `message_start`.
+    message: Option<StreamMessage>,
+    /// Sent with `message_delta`.
+    usage: Option<ApiUsage>,
+    /// Sent with `content_block_start`.
+    content_block: Option<StreamContentBlock>,
+}
+
+#[derive(Deserialize)]
+struct StreamContentBlock {
+    #[serde(rename = "type")]
+    block_type: String,
+    data: Option<String>,
+    id: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamMessage {
+    usage: Option<ApiUsage>,
+}
+
+/// Token counts as the API reports them. `message_delta` events only carry
+/// the fields that changed.
+#[derive(Deserialize)]
+struct ApiUsage {
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    cache_creation_input_tokens: Option<u64>,
+    cache_read_input_tokens: Option<u64>,
+}
+
+impl ApiUsage {
+    fn apply_to(&self, usage: &mut Usage) {
+        let fields = [
+            (self.input_tokens, &mut usage.input_tokens),
+            (self.output_tokens, &mut usage.output_tokens),
+            (
+                self.cache_creation_input_tokens,
+                &mut usage.cache_creation_input_tokens,
+            ),
+            (
+                self.cache_read_input_tokens,
+                &mut usage.cache_read_input_tokens,
+            ),
+        ];
+        for (value, field) in fields {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+    }
+}
+
+/// The body of an error response, and of an `error` stream event.
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    #[serde(rename = "type")]
+    error_type: String,
+    message: String,
+}
+
+/// An error reported by the Messages API, or the failure to reach it.
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    InvalidRequest(String),
+    Authentication(String),
+    Permission(String),
+    NotFound(String),
+    RequestTooLarge(String),
+    RateLimit(String),
+    Overloaded(String),
+    Api(String),
+    /// The request never got a complete response.
+    Network(String),
+    /// A response that doesn't follow the error schema.
+    Unexpected {
+        status: Option<u16>,
+        message: String,
+    },
+}
+
+impl ApiError {
+    fn from_detail(detail: ErrorDetail) -> Self {
+        let message = detail.message;
+        match detail.error_type.as_str() {
+            "invalid_request_error" => Self::InvalidRequest(message),
+            "authentication_error" => Self::Authentication(message),
+            "permission_error" => Self::Permission(message),
+            "not_found_error" => Self::NotFound(message),
+            "request_too_large" => Self::RequestTooLarge(message),
+            "rate_limit_error" => Self::RateLimit(message),
+            "overloaded_error" => Self::Overloaded(message),
+            "api_error" => Self::Api(message),
+            other => Self::Unexpected {
+                status: None,
+                message: format!("{}: {}", other, message),
+            },
+        }
+    }
+
+    /// Parses an error response, falling back to the status code when the
+    /// body isn't an API error (e.g. from a proxy).
+    fn from_response(status: StatusCode, body: &str) -> Self {
+        if let Ok(parsed) = serde_json::from_str::<ErrorBody>(body) {
+            return match Self::from_detail(parsed.error) {
+                Self::Unexpected { message, .. } => Self::Unexpected {
+                    status: Some(status.as_u16()),
+                    message,
+                },
+                error => error,
+            };
+        }
+        let message = if body.trim().is_empty() {
+            status.to_string()
+        } else {
+            body.trim().to_string()
+        };
+        match status.as_u16() {
+            400 => Self::InvalidRequest(message),
+            401 => Self::Authentication(message),
+            403 => Self::Permission(message),
+            404 => Self::NotFound(message),
+            413 => Self::RequestTooLarge(message),
+            429 => Self::RateLimit(message),
+            529 => Self::Overloaded(message),
+            500..=599 => Self::Api(message),
+            code => Self::Unexpected {
+                status: Some(code),
+                message,
+            },
+        }
+    }
 }
 
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Self::Unexpected {
+                status: e.status().map(|s| s.as_u16()),
+                message: e.to_string(),
+            }
+        } else {
+            Self::Network(e.to_string())
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidRequest(m) => write!(f, "invalid request: {}", m),
+            Self::Authentication(m) => write!(f, "authentication failed: {}", m),
+            Self::Permission(m) => write!(f, "permission denied: {}", m),
+            Self::NotFound(m) => write!(f, "not found: {}", m),
+            Self::RequestTooLarge(m) => write!(f, "request too large: {}", m),
+            Self::RateLimit(m) => write!(f, "rate limited: {}", m),
+            Self::Overloaded(m) => write!(f, "overloaded: {}", m),
+            Self::Api(m) => write!(f, "API error: {}", m),
+            Self::Network(m) => write!(f, "network error: {}", m),
+            Self::Unexpected {
+                status: Some(status),
+                message,
+            } => write!(f, "unexpected response ({}): {}", status, message),
+            Self::Unexpected { message, .. } => write!(f, "unexpected response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
 pub enum StreamChunk {
     Text(String),
+    /// Token usage of the reply so far.
+    Usage(Usage),
+    /// Why the model stopped generating.
+    Stop(String),
+    /// A new thinking block begins; deltas that follow extend it.
+    ThinkingStart(ThinkingBlock),
+    Thinking(String),
+    Signature(String),
+    /// A complete tool call, sent once its input has fully arrived.
+    ToolUse(ToolCall),
+    /// The request failed and will be sent again after a wait.
+    Retry(Retry),
     Done,
-    Error(String),
+    Error(ApiError),
+}
+
+pub struct Retry {
+    pub attempt: u32,
+    pub max_retries: u32,
+    pub delay: Duration,
+    pub reason: String,
 }
 
 pub struct ApiClient {
     client: reqwest::Client,
     api_key: String,
     pub model: String,
+    base_url: String,
+    max_tokens: u32,
+    temperature: Option<f32>,
+    retry: RetryConfig,
 }
 
+/// Tokens of a typical image; the real count depends on its dimensions.
+const IMAGE_TOKENS: u64 = 1600;
+
 const DEFAULT_MODEL: &str = "claude-sonnet-4-20250514";
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
 
 impl ApiClient {
-    pub fn new() -> Result<Self> {
-        let api_key = std::env::var("ANTHROPIC_API_KEY")
-            .map_err(|_| anyhow!("ANTHROPIC_API_KEY not set"))?;
+    pub fn new(config: &Config) -> Result<Self> {
+        let api_key =
+            std::env::var("ANTHROPIC_API_KEY").map_err(|_| anyhow!("ANTHROPIC_API_KEY not set"))?;
 
-        let model = std::env::var("CLAUDE_MODEL").unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+        // The environment overrides the config file
+        let model = std::env::var("CLAUDE_MODEL")
+            .ok()
+            .or_else(|| config.model.clone())
+            .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        let base_url = std::env::var("ANTHROPIC_BASE_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .or_else(|| config.api_base_url.clone())
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
 
         Ok(Self {
             client: reqwest::Client::new(),
             api_key,
             model,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+            retry: config.retry.clone(),
         })
     }
 
+    fn messages_url(&self) -> String {
+        format!("{}/v1/messages", self.base_url)
+    }
+
     pub fn set_model(&mut self, model: String) {
         self.model = model;
     }
@@ -84,38 +405,55 @@ impl ApiClient {
         &self.model
     }
 
-    fn build_request(&self, messages: &[Message], system_prompt: Option<&str>, stream: bool, model_override: Option<&str>) -> ApiRequest {
-        let api_messages: Vec<ApiMessage> = messages
-            .iter()
-            .map(|m| ApiMessage {
-                role: match m.role {
-                    Role::User => "user".to_string(),
-                    Role::Assistant => "assistant".to_string(),
-                },
-                content: m.content.clone(),
-            })
-            .collect();
-
+    fn build_request(
+        &self,
+        messages: &[Message],
+        system_prompt: Option<&str>,
+        stream: bool,
+        model_override: Option<&str>,
+        thinking_budget: Option<u32>,
+        tools: &[ToolDefinition],
+    ) -> ApiRequest {
+        // Thinking counts towards max_tokens and doesn't allow a custom
+        // temperature
+        let (max_tokens, temperature) = match thinking_budget {
+            Some(budget) => (self.max_tokens + budget, None),
+            None => (self.max_tokens, self.temperature),
+        };
         ApiRequest {
             model: model_override.unwrap_or(&self.model).to_string(),
-            max_tokens: 4096,
+            max_tokens,
             system: system_prompt.map(|s| s.to_string()),
-            messages: api_messages,
+            temperature,
+            thinking: thinking_budget.map(|budget_tokens| ThinkingConfig {
+                kind: "enabled",
+                budget_tokens,
+            }),
+            messages: api_messages(messages, thinking_budget.is_some()),
+            tools: tools.to_vec(),
             stream,
         }
     }
 
-    pub async fn send_message(
+    /// Asks the API how many input tokens a request with these messages
+    /// would use. Nothing is sent to the model and no retries are made.
+    pub async fn count_tokens(
         &self,
         messages: &[Message],
         system_prompt: Option<&str>,
         model_override: Option<&str>,
-    ) -> Result<String> {
-        let request = self.build_request(messages, system_prompt, false, model_override);
+        tools: &[ToolDefinition],
+    ) -> Result<u64, ApiError> {
+        let request = CountTokensRequest {
+            model: model_override.unwrap_or(&self.model).to_string(),
+            system: system_prompt.map(|s| s.to_string()),
+            messages: api_messages(messages, false),
+            tools: tools.to_vec(),
+        };
 
         let response = self
             .client
-            .post("https://api.anthropic.com/v1/messages")
+            .post(format!("{}/v1/messages/count_tokens", self.base_url))
             .header("x-api-key", &self.api_key)
             .header("anthropic-version", "2023-06-01")
             .header("content-type", "application/json")
@@ -126,16 +464,132 @@ impl ApiClient {
         if !response.status().is_success() {
             let status = response.status();
             let text = response.text().await.unwrap_or_default();
-            return Err(anyhow!("API error {}: {}", status, text));
+            return Err(ApiError::from_response(status, &text));
+        }
+
+        let count: CountTokensResponse = response.json().await?;
+        Ok(count.input_tokens)
+    }
+
+    /// Posts `request`, retrying connection failures and retryable statuses
+    /// with exponential backoff. Each wait is announced on `tx`. Returns the
+    /// last response once it succeeds, can't be retried or the retry budget
+    /// is spent.
+    async fn post_with_retry(
+        &self,
+        request: &ApiRequest,
+        tx: Option<&mpsc::Sender<StreamChunk>>,
+    ) -> Result<reqwest::Response, ApiError> {
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .client
+                .post(self.messages_url())
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", "2023-06-01")
+                .header("content-type", "application/json")
+                .json(request)
+                .send()
+                .await;
+
+            let retries_left = attempt < self.retry.max_retries;
+            let (delay, reason) = match result {
+                Ok(response) if !retries_left || !should_retry(&response) => return Ok(response),
+                Ok(response) => (
+                    self.retry_delay(response.headers(), attempt),
+                    retry_reason(response.status()),
+                ),
+                Err(e) if !retries_left || !(e.is_connect() || e.is_timeout()) => {
+                    return Err(e.into())
+                }
+                Err(_) => (
+                    backoff(&self.retry, attempt),
+                    "connection failed".to_string(),
+                ),
+            };
+
+            attempt += 1;
+            if let Some(tx) = tx {
+                let retry = Retry {
+                    attempt,
+                    max_retries: self.retry.max_retries,
+                    delay,
+                    reason,
+                };
+                let _ = tx.send(StreamChunk::Retry(retry)).await;
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// The wait before the next attempt: whatever the server asked for via
+    /// `retry-after` or an exhausted rate limit's reset time, or exponential
+    /// backoff otherwise.
+    fn retry_delay(&self, headers: &HeaderMap, attempt: u32) -> Duration {
+        let max = Duration::from_secs(self.retry.max_delay_secs);
+        match server_delay(headers) {
+            Some(delay) => delay.min(max),
+            None => backoff(&self.retry, attempt),
+        }
+    }
+
+    pub async fn send_message(
+        &self,
+        messages: &[Message],
+        system_prompt: Option<&str>,
+        model_override: Option<&str>,
+        thinking_budget: Option<u32>,
+        tools: &[ToolDefinition],
+    ) -> Result<Message, ApiError> {
+        let request = self.build_request(
+            messages,
+            system_prompt,
+            false,
+            model_override,
+            thinking_budget,
+            tools,
+        );
+
+        let response = self.post_with_retry(&request, None).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(ApiError::from_response(status, &text));
         }
 
         let api_response: ApiResponse = response.json().await?;
 
-        api_response
-            .content
-            .first()
-            .and_then(|block| block.text.clone())
-            .ok_or_else(|| anyhow!("No text in response"))
+        let mut message = Message::new(Role::Assistant, String::new());
+        message.model = Some(request.model);
+        message.stop_reason = api_response.stop_reason;
+        if let Some(reported) = &api_response.usage {
+            let mut usage = Usage::default();
+            reported.apply_to(&mut usage);
+            message.usage = Some(usage);
+        }
+        for block in api_response.content {
+            match block.block_type.as_str() {
+                "text" => message.content.push_str(&block.text.unwrap_or_default()),
+                "thinking" => message.thinking.push(ThinkingBlock::Thinking {
+                    thinking: block.thinking.unwrap_or_default(),
+                    signature: block.signature.unwrap_or_default(),
+                }),
+                "redacted_thinking" => message.thinking.push(ThinkingBlock::Redacted {
+                    data: block.data.unwrap_or_default(),
+                }),
+                "tool_use" => message.tool_calls.push(ToolCall {
+                    id: block.id.unwrap_or_default(),
+                    name: block.name.unwrap_or_default(),
+                    input: block
+                        .input
+                        .unwrap_or_else(|| Value::Object(Default::default())),
+                    result: None,
+                }),
+                _ => {}
+            }
+        }
+        Ok(message)
     }
 
     pub async fn send_message_streaming(
@@ -143,29 +597,35 @@ impl ApiClient {
         messages: &[Message],
         system_prompt: Option<&str>,
         model_override: Option<&str>,
+        thinking_budget: Option<u32>,
+        tools: &[ToolDefinition],
         tx: mpsc::Sender<StreamChunk>,
-    ) -> Result<()> {
-        let request = self.build_request(messages, system_prompt, true, model_override);
+    ) -> Result<(), ApiError> {
+        let request = self.build_request(
+            messages,
+            system_prompt,
+            true,
+            model_override,
+            thinking_budget,
+            tools,
+        );
 
-        let response = self
-            .client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("content-type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
+        let response = self.post_with_retry(&request, Some(&tx)).await?;
 
         if !response.status().is_success() {
             let status = response.status();
             let text = response.text().await.unwrap_or_default();
-            let _ = tx.send(StreamChunk::Error(format!("API error {}: {}", status, text))).await;
+            let _ = tx
+                .send(StreamChunk::Error(ApiError::from_response(status, &text)))
+                .await;
             return Ok(());
         }
 
         let mut stream = response.bytes_stream();
         let mut buffer = String::new();
+        let mut usage = Usage::default();
+        // Id, name and input JSON so far of the tool call being streamed
+        let mut tool_use: Option<(String, String, String)> = None;
 
         while let Some(chunk_result) = stream.next().await {
             match chunk_result {
@@ -177,25 +637,120 @@ impl ApiClient {
                         let line = buffer[..newline_pos].to_string();
                         buffer = buffer[newline_pos + 1..].to_string();
 
-                        if line.starts_with("data: ") {
-                            let json_str = &line[6..];
+                        if let Some(json_str) = line.strip_prefix("data: ") {
                             if let Ok(event) = serde_json::from_str::<StreamEvent>(json_str) {
                                 match event.event_type.as_str() {
+                                    "message_start" | "message_delta" => {
+                                        if let Some(reason) =
+                                            event.delta.and_then(|d| d.stop_reason)
+                                        {
+                                            let _ = tx.send(StreamChunk::Stop(reason)).await;
+                                        }
+                                        let reported = event.usage.as_ref().or(event
+                                            .message
+                                            .as_ref()
+                                            .and_then(|m| m.usage.as_ref()));
+                                        if let Some(reported) = reported {
+                                            reported.apply_to(&mut usage);
+                                            let _ = tx.send(StreamChunk::Usage(usage)).await;
+                                        }
+                                    }
+                                    "content_block_start" => {
+                                        let block = event.content_block.and_then(|b| {
+                                            match b.block_type.as_str() {
+                                                "thinking" => Some(ThinkingBlock::Thinking {
+                                                    thinking: String::new(),
+                                                    signature: String::new(),
+                                                }),
+                                                "redacted_thinking" => {
+                                                    Some(ThinkingBlock::Redacted {
+                                                        data: b.data.unwrap_or_default(),
+                                                    })
+                                                }
+                                                "tool_use" => {
+                                                    tool_use = Some((
+                                                        b.id.unwrap_or_default(),
+                                                        b.name.unwrap_or_default(),
+                                                        String::new(),
+                                                    ));
+                                                    None
+                                                }
+                                                _ => None,
+                                            }
+                                        });
+                                        if let Some(block) = block {
+                                            let _ =
+                                                tx.send(StreamChunk::ThinkingStart(block)).await;
+                                        }
+                                    }
                                     "content_block_delta" => {
                                         if let Some(delta) = event.delta {
-                                            if delta.delta_type.as_deref() == Some("text_delta") {
-                                                if let Some(text) = delta.text {
-                                                    let _ = tx.send(StreamChunk::Text(text)).await;
+                                            let chunk = match delta.delta_type.as_deref() {
+                                                Some("text_delta") => {
+                                                    delta.text.map(StreamChunk::Text)
+                                                }
+                                                Some("thinking_delta") => {
+                                                    delta.thinking.map(StreamChunk::Thinking)
+                                                }
+                                                Some("signature_delta") => {
+                                                    delta.signature.map(StreamChunk::Signature)
                                                 }
+                                                Some("input_json_delta") => {
+                                                    if let (Some((_, _, json)), Some(part)) =
+                                                        (tool_use.as_mut(), delta.partial_json)
+                                                    {
+                                                        json.push_str(&part);
+                                                    }
+                                                    None
+                                                }
+                                                _ => None,
+                                            };
+                                            if let Some(chunk) = chunk {
+                                                let _ = tx.send(chunk).await;
                                             }
                                         }
                                     }
+                                    "content_block_stop" => {
+                                        if let Some((id, name, json)) = tool_use.take() {
+                                            // A call without arguments may send no input at all
+                                            let input = if json.trim().is_empty() {
+                                                Ok(Value::Object(Default::default()))
+                                            } else {
+                                                serde_json::from_str(&json)
+                                            };
+                                            let chunk = match input {
+                                                Ok(input) => StreamChunk::ToolUse(ToolCall {
+                                                    id,
+                                                    name,
+                                                    input,
+                                                    result: None,
+                                                }),
+                                                Err(e) => {
+                                                    StreamChunk::Error(ApiError::Unexpected {
+                                                        status: None,
+                                                        message: format!(
+                                                            "invalid input for tool {}: {}",
+                                                            name, e
+                                                        ),
+                                                    })
+                                                }
+                                            };
+                                            let _ = tx.send(chunk).await;
+                                        }
+                                    }
                                     "message_stop" => {
                                         let _ = tx.send(StreamChunk::Done).await;
                                         return Ok(());
                                     }
                                     "error" => {
-                                        let _ = tx.send(StreamChunk::Error("Stream error".to_string())).await;
+                                        let error = match event.error {
+                                            Some(detail) => ApiError::from_detail(detail),
+                                            None => ApiError::Unexpected {
+                                                status: None,
+                                                message: json_str.to_string(),
+                                            },
+                                        };
+                                        let _ = tx.send(StreamChunk::Error(error)).await;
                                         return Ok(());
                                     }
                                     _ => {}
@@ -205,7 +760,7 @@ impl ApiClient {
                     }
                 }
                 Err(e) => {
-                    let _ = tx.send(StreamChunk::Error(e.to_string())).await;
+                    let _ = tx.send(StreamChunk::Error(e.into())).await;
                     return Ok(());
                 }
             }
@@ -215,3 +770,556 @@ impl ApiClient {
         Ok(())
     }
 }
+
+/// Converts the history to API messages. Thinking blocks are only sent
+/// while thinking is enabled, and only once their signature is complete.
+/// Tool results follow the reply that asked for them as a user turn; calls
+/// that never ran are left out.
+fn api_messages(messages: &[Message], include_thinking: bool) -> Vec<ApiMessage> {
+    let mut turns = Vec::new();
+    for m in messages {
+        let mut blocks: Vec<ApiContentBlock> = m
+            .thinking
+            .iter()
+            .filter(|_| include_thinking)
+            .filter_map(|block| match block {
+                ThinkingBlock::Thinking {
+                    thinking,
+                    signature,
+                } if !signature.is_empty() => Some(ApiContentBlock::Thinking {
+                    thinking: thinking.clone(),
+                    signature: signature.clone(),
+                }),
+                ThinkingBlock::Thinking { .. } => None,
+                ThinkingBlock::Redacted { data } => {
+                    Some(ApiContentBlock::RedactedThinking { data: data.clone() })
+                }
+            })
+            .collect();
+        // Images go before the text that asks about them
+        for attachment in &m.attachments {
+            blocks.push(match &attachment.data {
+                Some(data) => ApiContentBlock::Image {
+                    source: ImageSource {
+                        kind: "base64",
+                        media_type: attachment.media_type.clone(),
+                        data: data.to_string(),
+                    },
+                },
+                None => ApiContentBlock::Text {
+                    text: format!("[Image no longer available: {}]", attachment.path.display()),
+                },
+            });
+        }
+        if !m.content.is_empty() {
+            blocks.push(ApiContentBlock::Text {
+                text: m.content.clone(),
+            });
+        }
+
+        let mut results = Vec::new();
+        for call in &m.tool_calls {
+            let Some(result) = &call.result else {
+                continue;
+            };
+            blocks.push(ApiContentBlock::ToolUse {
+                id: call.id.clone(),
+                name: call.name.clone(),
+                input: call.input.clone(),
+            });
+            results.push(ApiContentBlock::ToolResult {
+                tool_use_id: call.id.clone(),
+                content: result.content.clone(),
+                is_error: result.is_error,
+            });
+        }
+
+        let role = match m.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        push_turn(&mut turns, role, blocks);
+        push_turn(&mut turns, "user", results);
+    }
+    turns
+}
+
+/// Appends content to the conversation, merging it into the last turn when
+/// that has the same role so roles keep alternating. A turn that is a
+/// single text block is sent as a plain string.
+fn push_turn(turns: &mut Vec<ApiMessage>, role: &str, mut blocks: Vec<ApiContentBlock>) {
+    if blocks.is_empty() {
+        return;
+    }
+    if let Some(last) = turns.last_mut().filter(|t| t.role == role) {
+        let mut merged = match std::mem::replace(&mut last.content, ApiContent::Blocks(Vec::new()))
+        {
+            ApiContent::Text(text) => vec![ApiContentBlock::Text { text }],
+            ApiContent::Blocks(blocks) => blocks,
+        };
+        merged.append(&mut blocks);
+        last.content = ApiContent::Blocks(merged);
+        return;
+    }
+    let content = match blocks.as_mut_slice() {
+        [ApiContentBlock::Text { text }] => ApiContent::Text(std::mem::take(text)),
+        _ => ApiContent::Blocks(blocks),
+    };
+    turns.push(ApiMessage {
+        role: role.to_string(),
+        content,
+    });
+}
+
+/// A rough input token count for when the API can't be asked: about four
+/// characters per token, plus a few tokens of framing per message. Images
+/// count as a mid-sized one.
+pub fn estimate_tokens(messages: &[Message], system_prompt: Option<&str>) -> u64 {
+    let text_tokens = |text: &str| (text.chars().count() as u64).div_ceil(4);
+    let tool_tokens = |call: &ToolCall| {
+        text_tokens(&call.input.to_string())
+            + call.result.as_ref().map_or(0, |r| text_tokens(&r.content))
+    };
+    let system = system_prompt.map_or(0, text_tokens);
+    system
+        + messages
+            .iter()
+            .map(|m| {
+                text_tokens(&m.content)
+                    + m.tool_calls.iter().map(tool_tokens).sum::<u64>()
+                    + m.attachments.len() as u64 * IMAGE_TOKENS
+                    + 4
+            })
+            .sum::<u64>()
+}
+
+fn should_retry(response: &reqwest::Response) -> bool {
+    // The API can say explicitly whether a retry is worthwhile
+    match response
+        .headers()
+        .get("x-should-retry")
+        .and_then(|v| v.to_str().ok())
+    {
+        Some("true") => return true,
+        Some("false") => return false,
+        _ => {}
+    }
+    let status = response.status();
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::CONFLICT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+        || status.as_u16() == 529
+}
+
+fn retry_reason(status: StatusCode) -> String {
+    match status.as_u16() {
+        429 => "rate limited".to_string(),
+        529 => "overloaded".to_string(),
+        _ => status.to_string(),
+    }
+}
+
+/// The wait requested by the server, if any.
+/// Exponential backoff with up to 25% jitter so clients that failed
+/// together don't retry together.
+fn backoff(retry: &RetryConfig, attempt: u32) -> Duration {
+    let delay = Duration::from_millis(retry.base_delay_ms)
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(Duration::from_secs(retry.max_delay_secs));
+    delay.mul_f64(1.0 - 0.25 * fastrand::f64())
+}
+
+fn server_delay(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return Duration::try_from_secs_f64(ms.max(0.0) / 1000.0).ok();
+    }
+    if let Some(value) = header("retry-after") {
+        if let Ok(secs) = value.parse::<f64>() {
+            return Duration::try_from_secs_f64(secs.max(0.0)).ok();
+        }
+        if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+            return Some(until(date.with_timezone(&Utc)));
+        }
+    }
+
+    // Wait for the latest reset among the limits that are used up
+    ["requests", "tokens", "input-tokens", "output-tokens"]
+        .iter()
+        .filter(|limit| header(&format!("anthropic-ratelimit-{}-remaining", limit)) == Some("0"))
+        .filter_map(|limit| header(&format!("anthropic-ratelimit-{}-reset", limit)))
+        .filter_map(|reset| DateTime::parse_from_rfc3339(reset).ok())
+        .map(|reset| until(reset.with_timezone(&Utc)))
+        .max()
+}
+
+fn until(time: DateTime<Utc>) -> Duration {
+    (time - Utc::now()).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachment::Attachment;
+    use crate::conversation::ToolResult;
+    use chrono::TimeDelta;
+    use reqwest::header::{HeaderName, HeaderValue};
+    use serde_json::json;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                    HeaderValue::from_str(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn in_secs(secs: i64) -> DateTime<Utc> {
+        Utc::now() + TimeDelta::seconds(secs)
+    }
+
+    fn turns(messages: &[Message], include_thinking: bool) -> Value {
+        serde_json::to_value(api_messages(messages, include_thinking)).unwrap()
+    }
+
+    fn user(content: &str) -> Message {
+        Message::new(Role::User, content.to_string())
+    }
+
+    fn assistant(content: &str) -> Message {
+        Message::new(Role::Assistant, content.to_string())
+    }
+
+    fn tool_call(id: &str, result: Option<(&str, bool)>) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            name: "read_file".to_string(),
+            input: json!({ "path": id }),
+            result: result.map(|(content, is_error)| ToolResult {
+                content: content.to_string(),
+                is_error,
+            }),
+        }
+    }
+
+    #[test]
+    fn single_text_turns_are_sent_as_strings() {
+        assert_eq!(
+            turns(&[user("Hi"), assistant("Hello")], false),
+            json!([
+                { "role": "user", "content": "Hi" },
+                { "role": "assistant", "content": "Hello" },
+            ])
+        );
+    }
+
+    #[test]
+    fn turns_of_the_same_role_are_merged() {
+        assert_eq!(
+            turns(&[user("One"), user("Two"), assistant("Three")], false),
+            json!([
+                { "role": "user", "content": [
+                    { "type": "text", "text": "One" },
+                    { "type": "text", "text": "Two" },
+                ] },
+                { "role": "assistant", "content": "Three" },
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_messages_are_left_out() {
+        assert_eq!(
+            turns(&[user("Hi"), assistant(""), user("Anyone?")], false),
+            json!([{ "role": "user", "content": [
+                { "type": "text", "text": "Hi" },
+                { "type": "text", "text": "Anyone?" },
+            ] }])
+        );
+    }
+
+    #[test]
+    fn images_go_before_the_text() {
+        let mut message = user("What is this?");
+        for (name, data) in [("cat.png", Some("aW1n")), ("gone.png", None)] {
+            message.attachments.push(Attachment {
+                path: name.into(),
+                media_type: "image/png".to_string(),
+                size: 3,
+                data: data.map(Into::into),
+            });
+        }
+        assert_eq!(
+            turns(&[message], false),
+            json!([{ "role": "user", "content": [
+                { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "aW1n" } },
+                { "type": "text", "text": "[Image no longer available: gone.png]" },
+                { "type": "text", "text": "What is this?" },
+            ] }])
+        );
+    }
+
+    #[test]
+    fn signed_thinking_is_replayed_first_when_enabled() {
+        let mut reply = assistant("42");
+        reply.thinking = vec![
+            ThinkingBlock::Thinking {
+                thinking: "Let me see".to_string(),
+                signature: "sig".to_string(),
+            },
+            // Interrupted before its signature arrived
+            ThinkingBlock::Thinking {
+                thinking: "Hmm".to_string(),
+                signature: String::new(),
+            },
+            ThinkingBlock::Redacted {
+                data: "secret".to_string(),
+            },
+        ];
+        let messages = [user("Question"), reply];
+
+        assert_eq!(
+            turns(&messages, true)[1],
+            json!({ "role": "assistant", "content": [
+                { "type": "thinking", "thinking": "Let me see", "signature": "sig" },
+                { "type": "redacted_thinking", "data": "secret" },
+                { "type": "text", "text": "42" },
+            ] })
+        );
+        assert_eq!(
+            turns(&messages, false)[1],
+            json!({ "role": "assistant", "content": "42" })
+        );
+    }
+
+    #[test]
+    fn tool_results_follow_the_reply_that_asked_for_them() {
+        let mut reply = assistant("Reading both.");
+        reply.tool_calls = vec![
+            tool_call("a", Some(("contents", false))),
+            tool_call("b", Some(("no such file", true))),
+            // Never ran, e.g. because the request was cancelled
+            tool_call("c", None),
+        ];
+        let messages = [user("Compare a and b"), reply, user("Thanks")];
+
+        assert_eq!(
+            turns(&messages, false),
+            json!([
+                { "role": "user", "content": "Compare a and b" },
+                { "role": "assistant", "content": [
+                    { "type": "text", "text": "Reading both." },
+                    { "type": "tool_use", "id": "a", "name": "read_file", "input": { "path": "a" } },
+                    { "type": "tool_use", "id": "b", "name": "read_file", "input": { "path": "b" } },
+                ] },
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "a", "content": "contents" },
+                    { "type": "tool_result", "tool_use_id": "b", "content": "no such file", "is_error": true },
+                    { "type": "text", "text": "Thanks" },
+                ] },
+            ])
+        );
+    }
+
+    #[test]
+    fn tool_rounds_keep_roles_alternating() {
+        let mut first = assistant("");
+        first.tool_calls = vec![tool_call("a", Some(("contents", false)))];
+        let messages = [user("Read a"), first, assistant("It says contents.")];
+
+        let turns = turns(&messages, false);
+        let roles: Vec<&str> = turns
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|turn| turn["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(roles, ["user", "assistant", "user", "assistant"]);
+        assert_eq!(turns[1]["content"][0]["type"], "tool_use");
+        assert_eq!(turns[2]["content"][0]["type"], "tool_result");
+    }
+
+    fn error_body(error_type: &str, message: &str) -> String {
+        json!({ "type": "error", "error": { "type": error_type, "message": message } }).to_string()
+    }
+
+    fn from_response(status: u16, body: &str) -> ApiError {
+        ApiError::from_response(StatusCode::from_u16(status).unwrap(), body)
+    }
+
+    #[test]
+    fn error_responses_map_to_their_type() {
+        assert!(matches!(
+            from_response(401, &error_body("authentication_error", "invalid x-api-key")),
+            ApiError::Authentication(m) if m == "invalid x-api-key"
+        ));
+        assert!(matches!(
+            from_response(429, &error_body("rate_limit_error", "Too many requests")),
+            ApiError::RateLimit(m) if m == "Too many requests"
+        ));
+        assert!(matches!(
+            from_response(529, &error_body("overloaded_error", "Overloaded")),
+            ApiError::Overloaded(m) if m == "Overloaded"
+        ));
+        assert!(matches!(
+            from_response(400, &error_body("invalid_request_error", "max_tokens: too large")),
+            ApiError::InvalidRequest(m) if m == "max_tokens: too large"
+        ));
+    }
+
+    #[test]
+    fn the_error_type_wins_over_the_status() {
+        assert!(matches!(
+            from_response(500, &error_body("overloaded_error", "Overloaded")),
+            ApiError::Overloaded(_)
+        ));
+    }
+
+    #[test]
+    fn unknown_error_types_keep_the_status() {
+        let error = from_response(418, &error_body("teapot_error", "short and stout"));
+        assert!(matches!(
+            &error,
+            ApiError::Unexpected { status: Some(418), message } if message == "teapot_error: short and stout"
+        ));
+        assert_eq!(
+            error.to_string(),
+            "unexpected response (418): teapot_error: short and stout"
+        );
+    }
+
+    #[test]
+    fn unparseable_bodies_fall_back_to_the_status() {
+        assert!(matches!(
+            from_response(401, "<html>Unauthorized</html>"),
+            ApiError::Authentication(m) if m == "<html>Unauthorized</html>"
+        ));
+        assert!(matches!(
+            from_response(429, "{\"error\": \"slow down\"}"),
+            ApiError::RateLimit(_)
+        ));
+        assert!(
+            matches!(from_response(529, ""), ApiError::Overloaded(m) if m == "529 <unknown status code>")
+        );
+        assert!(matches!(
+            from_response(502, "  Bad Gateway\n"),
+            ApiError::Api(m) if m == "Bad Gateway"
+        ));
+        assert!(matches!(
+            from_response(302, ""),
+            ApiError::Unexpected { status: Some(302), message } if message == "302 Found"
+        ));
+    }
+
+    #[test]
+    fn stream_error_events_map_to_their_type() {
+        let detail = |error_type: &str| ErrorDetail {
+            error_type: error_type.to_string(),
+            message: "m".to_string(),
+        };
+        assert!(matches!(
+            ApiError::from_detail(detail("overloaded_error")),
+            ApiError::Overloaded(_)
+        ));
+        assert!(matches!(
+            ApiError::from_detail(detail("api_error")),
+            ApiError::Api(_)
+        ));
+        assert!(matches!(
+            ApiError::from_detail(detail("request_too_large")),
+            ApiError::RequestTooLarge(_)
+        ));
+        assert!(matches!(
+            ApiError::from_detail(detail("mystery")),
+            ApiError::Unexpected { status: None, message } if message == "mystery: m"
+        ));
+    }
+
+    #[test]
+    fn server_delay_reads_retry_after_seconds() {
+        let delay = server_delay(&headers(&[("retry-after", "2")]));
+        assert_eq!(delay, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn server_delay_prefers_retry_after_ms() {
+        let delay = server_delay(&headers(&[
+            ("retry-after", "2"),
+            ("retry-after-ms", "1500"),
+        ]));
+        assert_eq!(delay, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn server_delay_reads_retry_after_dates() {
+        let date = in_secs(30).to_rfc2822();
+        let delay = server_delay(&headers(&[("retry-after", &date)])).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn server_delay_waits_for_the_latest_exhausted_limit() {
+        let soon = in_secs(10).to_rfc3339();
+        let later = in_secs(30).to_rfc3339();
+        let latest = in_secs(60).to_rfc3339();
+        let delay = server_delay(&headers(&[
+            ("anthropic-ratelimit-requests-remaining", "0"),
+            ("anthropic-ratelimit-requests-reset", &soon),
+            ("anthropic-ratelimit-tokens-remaining", "0"),
+            ("anthropic-ratelimit-tokens-reset", &later),
+            // Not used up, so its reset doesn't matter
+            ("anthropic-ratelimit-output-tokens-remaining", "1000"),
+            ("anthropic-ratelimit-output-tokens-reset", &latest),
+        ]))
+        .unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn server_delay_ignores_missing_and_unusable_headers() {
+        assert_eq!(server_delay(&HeaderMap::new()), None);
+        assert_eq!(server_delay(&headers(&[("retry-after", "soon")])), None);
+        assert_eq!(server_delay(&headers(&[("retry-after", "inf")])), None);
+        let past = in_secs(-30).to_rfc2822();
+        assert_eq!(
+            server_delay(&headers(&[("retry-after", &past)])),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_within_the_jitter_bounds() {
+        let retry = RetryConfig {
+            base_delay_ms: 100,
+            max_delay_secs: 60,
+            ..RetryConfig::default()
+        };
+        for attempt in 0..5 {
+            let full = Duration::from_millis(100 << attempt);
+            for _ in 0..100 {
+                let delay = backoff(&retry, attempt);
+                assert!(delay <= full && delay >= full.mul_f64(0.75), "{:?}", delay);
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_stops_at_the_maximum_delay() {
+        let retry = RetryConfig {
+            base_delay_ms: 1000,
+            max_delay_secs: 5,
+            ..RetryConfig::default()
+        };
+        let max = Duration::from_secs(5);
+        for attempt in [3, 10, 40, u32::MAX] {
+            let delay = backoff(&retry, attempt);
+            assert!(delay <= max && delay >= max.mul_f64(0.75), "{:?}", delay);
+        }
+    }
+}
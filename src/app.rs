@@ -1,35 +1,199 @@
-use crate::api::ApiClient;
-use crate::conversation::{Conversation, Message, Role};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+use crate::api::{self, ApiClient, ApiError, Retry, ToolDefinition};
+use crate::attachment::{self, Attachment};
+use crate::browser::{BrowseInput, Browser};
+use crate::builtin_tools;
+use crate::config::{Config, McpServerConfig};
+use crate::conversation::{
+    Conversation, Message, Role, ThinkingBlock, ToolCall, ToolResult, Usage,
+};
+use crate::mcp::{McpServer, McpSession, McpStatus, McpToolHandle};
 use crate::storage;
+use crate::text;
+use crate::tools::ToolRegistry;
+use crate::ui::LayoutCache;
+
+/// Pastes with more lines than this are shown as a placeholder in the input.
+const PASTE_COLLAPSE_LINES: usize = 10;
+
+/// Smallest thinking budget the API accepts, and the one `/think` uses when
+/// no budget is given.
+const MIN_THINKING_BUDGET: u32 = 1024;
+const DEFAULT_THINKING_BUDGET: u32 = 4096;
+
+/// How long the input has to stay unchanged before its tokens are counted
+/// by the API.
+const TOKEN_COUNT_DELAY: Duration = Duration::from_millis(600);
+
+/// Keys pressed this soon after an approval prompt appears are ignored, so
+/// a keystroke meant for something else can't answer it.
+const APPROVAL_GUARD: Duration = Duration::from_millis(500);
+
+/// Results reported to the model for calls that were not run.
+const DENIED_RESULT: &str = "The user denied this tool call.";
+const DRY_RUN_RESULT: &str = "Dry run: the tool was not run.";
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum Mode {
     Normal,
     Insert,
     Help,
+    Browse,
+    /// A tool call is waiting for the user's approval.
+    Approve,
+    /// The MCP server overview.
+    Mcp,
+}
+
+/// What a submitted input asks the event loop to send.
+#[derive(Clone, Copy, PartialEq)]
+pub enum RequestKind {
+    /// A reply to the user message just added.
+    Reply,
+    /// More of the last assistant message, appended to it.
+    Continue,
+}
+
+/// Tags the events of one request or tool run, so ones still queued from a
+/// cancelled or replaced request can be told apart.
+#[derive(Clone, Copy, PartialEq)]
+pub struct RequestId {
+    pub conversation_id: Uuid,
+    pub generation: u64,
+}
+
+/// A tool call waiting for the user to allow or deny it.
+pub struct Approval {
+    pub conversation_id: Uuid,
+    pub call: ToolCall,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ApprovalChoice {
+    Once,
+    /// Allow this tool for the rest of the conversation.
+    Always,
+    Deny,
+}
+
+/// What to do next with a reply's tool calls.
+pub enum ToolStep {
+    Run(ToolCall),
+    /// Nothing to start: a call is running or waiting for approval, or the
+    /// turn was stopped.
+    Wait,
+    /// Every call has a result; send them back to the model.
+    Done,
+}
+
+/// Text that can be edited in an external editor.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EditTarget {
+    Input,
+    SystemPrompt,
+}
+
+/// Projected input tokens of the next request: the history plus whatever is
+/// in the input box. Starts as a local estimate and is replaced by the API's
+/// count once the input settles.
+pub struct TokenCount {
+    fingerprint: u64,
+    changed_at: Instant,
+    requested: bool,
+    pub tokens: u64,
+    pub exact: bool,
+}
+
+/// A token count to run in the background.
+pub struct CountRequest {
+    pub fingerprint: u64,
+    pub messages: Vec<Message>,
+    pub system_prompt: Option<String>,
+    pub model: Option<String>,
+    pub tools: Vec<ToolDefinition>,
 }
 
 pub struct App {
     pub input: String,
     pub cursor_position: usize,
+    /// First visible row of the input box when it overflows.
+    pub input_scroll: usize,
+    /// Rows the input box may grow to before it scrolls.
+    pub max_input_height: usize,
+    /// Collapsed pastes in the input: placeholder text and the full paste.
+    pub pastes: Vec<(String, String)>,
+    /// Images to send with the next message.
+    pub attachments: Vec<Attachment>,
     pub mode: Mode,
     pub should_quit: bool,
     pub conversations: Vec<Conversation>,
     pub active_tab: usize,
-    pub api_client: Option<ApiClient>,
-    pub is_loading: bool,
+    pub api_client: Option<Arc<ApiClient>>,
+    pub config: Config,
+    /// Tools offered to the model with every request.
+    pub tools: ToolRegistry,
+    /// Report tool calls back without running them.
+    pub dry_run: bool,
+    /// Tool calls waiting for approval, oldest first.
+    pub approvals: VecDeque<Approval>,
+    /// Mode to return to once no approval is pending.
+    mode_before_approval: Mode,
+    /// When the approval prompt last showed a new call.
+    approval_shown: Instant,
+    /// Calls of each conversation's last reply that haven't started yet.
+    tool_queue: HashMap<Uuid, VecDeque<ToolCall>>,
+    /// Configured MCP servers, in name order.
+    pub mcp_servers: Vec<McpServer>,
+    /// Server selected in the MCP overview.
+    pub mcp_selected: usize,
+    /// Servers the event loop should launch on its next iteration.
+    pending_mcp_starts: Vec<String>,
     pub error_message: Option<String>,
     pub status_message: Option<String>,
     pub current_model: Option<String>,
     pub pending_model_change: Option<String>,
+    /// The running request or tool of each conversation, with its generation.
+    pub requests: HashMap<Uuid, (u64, AbortHandle)>,
+    next_request: u64,
+    /// Requests waiting to be retried, with the time the next attempt starts.
+    pub retries: HashMap<Uuid, (Instant, Retry)>,
+    pub browser: Option<Browser>,
+    pub show_raw_markdown: bool,
+    /// Extended thinking budget in tokens, or `None` when thinking is off.
+    pub thinking_budget: Option<u32>,
+    /// Whether thinking sections are expanded.
+    pub show_thinking: bool,
+    pub layouts: HashMap<Uuid, LayoutCache>,
+    /// Set by a keybinding; the event loop suspends the TUI and opens the
+    /// external editor on the next iteration.
+    pub pending_edit: Option<EditTarget>,
+    /// Tokens used and their estimated cost since the app started.
+    pub session_usage: Usage,
+    pub session_cost: f64,
+    pub token_count: Option<TokenCount>,
+    /// Usage already recorded on replies being continued, which the new
+    /// request's usage is added to.
+    usage_base: HashMap<Uuid, Usage>,
 }
 
 impl App {
     pub fn new() -> Self {
-        let (api_client, current_model) = match ApiClient::new() {
+        let (config, config_error) = Config::load();
+        storage::set_storage_dir(config.storage_dir());
+
+        let (api_client, current_model) = match ApiClient::new(&config) {
             Ok(client) => {
                 let model = client.get_model().to_string();
-                (Some(client), Some(model))
+                (Some(Arc::new(client)), Some(model))
             }
             Err(e) => {
                 eprintln!("Warning: {}", e);
@@ -37,19 +201,60 @@ impl App {
             }
         };
 
+        // Restore the tabs from the previous run, if any
+        let (conversations, active_tab) = match storage::load_session() {
+            Ok((conversations, active)) if !conversations.is_empty() => (conversations, active),
+            _ => (vec![Self::blank_conversation(&config)], 0),
+        };
+
+        let tools = Self::builtin_tools(&config);
+        let dry_run = config.tools.dry_run;
+        let mcp_servers: Vec<McpServer> = config
+            .mcp_servers
+            .iter()
+            .map(|(name, server)| McpServer::new(name.clone(), server.clone()))
+            .collect();
+        let pending_mcp_starts = mcp_servers.iter().map(|s| s.name.clone()).collect();
+
         Self {
             input: String::new(),
             cursor_position: 0,
+            input_scroll: 0,
+            max_input_height: config.input_max_height,
+            pastes: Vec::new(),
+            attachments: Vec::new(),
             mode: Mode::Normal,
             should_quit: false,
-            conversations: vec![Conversation::new()],
-            active_tab: 0,
+            conversations,
+            active_tab,
             api_client,
-            is_loading: false,
-            error_message: None,
+            config,
+            tools,
+            dry_run,
+            approvals: VecDeque::new(),
+            mode_before_approval: Mode::Normal,
+            approval_shown: Instant::now(),
+            tool_queue: HashMap::new(),
+            mcp_servers,
+            mcp_selected: 0,
+            pending_mcp_starts,
+            error_message: config_error.map(|e| format!("Config error: {}", e)),
             status_message: None,
             current_model,
             pending_model_change: None,
+            requests: HashMap::new(),
+            next_request: 0,
+            retries: HashMap::new(),
+            browser: None,
+            show_raw_markdown: false,
+            thinking_budget: None,
+            show_thinking: false,
+            layouts: HashMap::new(),
+            pending_edit: None,
+            session_usage: Usage::default(),
+            session_cost: 0.0,
+            token_count: None,
+            usage_base: HashMap::new(),
         }
     }
 
@@ -61,35 +266,247 @@ impl App {
         &mut self.conversations[self.active_tab]
     }
 
+    pub fn conversation(&self, id: Uuid) -> Option<&Conversation> {
+        self.conversations.iter().find(|c| c.id == id)
+    }
+
+    pub fn conversation_mut(&mut self, id: Uuid) -> Option<&mut Conversation> {
+        self.conversations.iter_mut().find(|c| c.id == id)
+    }
+
+    fn builtin_tools(config: &Config) -> ToolRegistry {
+        let mut tools = ToolRegistry::new();
+        if config.tools.enabled {
+            let root = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+            let timeout = Duration::from_secs(config.tools.shell_timeout_secs);
+            builtin_tools::register(&mut tools, root, timeout);
+        }
+        tools
+    }
+
+    /// Rebuilds the tool registry from the built-in tools and those of the
+    /// running MCP servers.
+    fn rebuild_tools(&mut self) {
+        let mut tools = Self::builtin_tools(&self.config);
+        for server in &self.mcp_servers {
+            if let Some(session) = server.session() {
+                for tool in &session.tools {
+                    tools.register(Arc::new(McpToolHandle::new(
+                        &server.name,
+                        session.clone(),
+                        tool.clone(),
+                    )));
+                }
+            }
+        }
+        self.tools = tools;
+    }
+
+    /// The MCP servers to launch, with the generation their events will
+    /// carry.
+    pub fn take_mcp_starts(&mut self) -> Vec<(String, u64, McpServerConfig)> {
+        let names = std::mem::take(&mut self.pending_mcp_starts);
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let server = self.mcp_servers.iter().find(|s| s.name == name)?;
+                Some((name, server.generation, server.config.clone()))
+            })
+            .collect()
+    }
+
+    pub fn mcp_started(
+        &mut self,
+        name: &str,
+        generation: u64,
+        result: Result<Arc<McpSession>, String>,
+    ) {
+        let Some(server) = self
+            .mcp_servers
+            .iter_mut()
+            .find(|s| s.name == name && s.generation == generation)
+        else {
+            // Restarted or removed while starting
+            if let Ok(session) = result {
+                session.shutdown();
+            }
+            return;
+        };
+        match result {
+            Ok(session) => server.status = McpStatus::Running(session),
+            Err(e) => {
+                server.status = McpStatus::Failed(e.clone());
+                self.set_error(format!("MCP server {}: {}", name, e));
+            }
+        }
+        self.rebuild_tools();
+    }
+
+    pub fn mcp_exited(&mut self, name: &str, generation: u64) {
+        let Some(server) = self
+            .mcp_servers
+            .iter_mut()
+            .find(|s| s.name == name && s.generation == generation)
+        else {
+            return;
+        };
+        server.status = McpStatus::Failed("exited".to_string());
+        self.set_error(format!("MCP server {} exited", name));
+        self.rebuild_tools();
+    }
+
+    /// Restarts the named MCP server, or all of them.
+    pub fn restart_mcp(&mut self, name: Option<&str>) {
+        let mut restarted = Vec::new();
+        for server in &mut self.mcp_servers {
+            if name.is_none() || name == Some(server.name.as_str()) {
+                server.restart();
+                restarted.push(server.name.clone());
+            }
+        }
+        if restarted.is_empty() {
+            self.set_error(match name {
+                Some(name) => format!("No MCP server named {}", name),
+                None => "No MCP servers configured".to_string(),
+            });
+            return;
+        }
+        self.status_message = Some(format!("Restarting {}", restarted.join(", ")));
+        self.pending_mcp_starts.extend(restarted);
+        self.rebuild_tools();
+    }
+
+    /// Brings the running MCP servers in line with the config: removed
+    /// servers stop, and new or changed ones (re)start.
+    fn sync_mcp_servers(&mut self) {
+        let configured = &self.config.mcp_servers;
+        self.mcp_servers.retain(|server| {
+            let keep = configured.get(&server.name) == Some(&server.config);
+            if !keep {
+                if let Some(session) = server.session() {
+                    session.shutdown();
+                }
+            }
+            keep
+        });
+        for (name, config) in configured {
+            if !self.mcp_servers.iter().any(|s| &s.name == name) {
+                self.mcp_servers
+                    .push(McpServer::new(name.clone(), config.clone()));
+                self.pending_mcp_starts.push(name.clone());
+            }
+        }
+        self.mcp_servers.sort_by(|a, b| a.name.cmp(&b.name));
+        self.mcp_selected = self
+            .mcp_selected
+            .min(self.mcp_servers.len().saturating_sub(1));
+    }
+
+    pub fn open_mcp(&mut self) {
+        if self.mcp_servers.is_empty() {
+            self.status_message = Some("No MCP servers configured".to_string());
+            return;
+        }
+        self.mcp_selected = self.mcp_selected.min(self.mcp_servers.len() - 1);
+        self.mode = Mode::Mcp;
+    }
+
+    pub fn select_next_mcp(&mut self) {
+        if self.mcp_selected + 1 < self.mcp_servers.len() {
+            self.mcp_selected += 1;
+        }
+    }
+
+    pub fn select_prev_mcp(&mut self) {
+        self.mcp_selected = self.mcp_selected.saturating_sub(1);
+    }
+
+    /// A new conversation seeded with the configured default system prompt.
+    fn blank_conversation(config: &Config) -> Conversation {
+        let mut conv = Conversation::new();
+        conv.system_prompt = config.system_prompt.clone();
+        conv
+    }
+
     pub fn new_conversation(&mut self) {
-        self.conversations.push(Conversation::new());
+        self.conversations
+            .push(Self::blank_conversation(&self.config));
         self.active_tab = self.conversations.len() - 1;
+        self.save_session();
+    }
+
+    /// Re-reads the config file and applies it. An invalid file leaves the
+    /// current settings untouched. A model picked with `/model` is kept.
+    pub fn reload_config(&mut self) {
+        let config = match Config::try_load() {
+            Ok(config) => config,
+            Err(e) => {
+                self.set_error(format!("Config error: {}", e));
+                return;
+            }
+        };
+
+        let old_default = self.api_client.as_ref().map(|c| c.get_model().to_string());
+        match ApiClient::new(&config) {
+            Ok(client) => {
+                let model_overridden = self.current_model != old_default;
+                if !model_overridden {
+                    self.current_model = Some(client.get_model().to_string());
+                }
+                self.api_client = Some(Arc::new(client));
+            }
+            Err(e) => {
+                self.api_client = None;
+                self.set_error(e.to_string());
+            }
+        }
+
+        storage::set_storage_dir(config.storage_dir());
+        self.max_input_height = config.input_max_height;
+        self.dry_run = config.tools.dry_run;
+        // Cached bubbles depend on the theme's bubble width
+        self.layouts.clear();
+        self.config = config;
+        self.sync_mcp_servers();
+        self.rebuild_tools();
+        self.status_message = Some("Config reloaded".to_string());
     }
 
     pub fn close_current_conversation(&mut self) {
         if self.conversations.len() > 1 {
-            self.conversations.remove(self.active_tab);
+            let conv = self.conversations.remove(self.active_tab);
+            if let Some((_, handle)) = self.requests.remove(&conv.id) {
+                handle.abort();
+            }
+            self.retries.remove(&conv.id);
+            self.layouts.remove(&conv.id);
+            self.drop_tool_calls(conv.id);
             if self.active_tab >= self.conversations.len() {
                 self.active_tab = self.conversations.len() - 1;
             }
+            self.save_session();
         }
     }
 
     pub fn next_tab(&mut self) {
         if self.active_tab < self.conversations.len() - 1 {
             self.active_tab += 1;
+            self.save_session();
         }
     }
 
     pub fn prev_tab(&mut self) {
         if self.active_tab > 0 {
             self.active_tab -= 1;
+            self.save_session();
         }
     }
 
     pub fn save_current_conversation(&mut self) {
         match storage::save_conversation(self.current_conversation()) {
             Ok(_) => {
+                self.current_conversation_mut().saved = true;
+                self.save_session();
                 self.status_message = Some("Conversation saved ✓".to_string());
             }
             Err(e) => {
@@ -98,6 +515,141 @@ impl App {
         }
     }
 
+    pub fn open_browser(&mut self) {
+        match storage::list_saved_conversations() {
+            Ok(list) if list.is_empty() => {
+                self.status_message = Some("No saved conversations".to_string());
+            }
+            Ok(list) => {
+                self.browser = Some(Browser::new(list));
+                self.mode = Mode::Browse;
+            }
+            Err(e) => {
+                self.set_error(format!("Failed to list conversations: {}", e));
+            }
+        }
+    }
+
+    pub fn close_browser(&mut self) {
+        self.browser = None;
+        self.mode = Mode::Normal;
+    }
+
+    pub fn open_selected_conversation(&mut self) {
+        let Some(path) = self
+            .browser
+            .as_ref()
+            .and_then(|b| b.selected_entry())
+            .map(|e| e.path.clone())
+        else {
+            return;
+        };
+        self.close_browser();
+        self.open_conversation_file(&path);
+    }
+
+    pub fn start_rename(&mut self) {
+        if let Some(browser) = self.browser.as_mut() {
+            if let Some(title) = browser.selected_entry().map(|e| e.title.clone()) {
+                browser.input = BrowseInput::Rename(title);
+            }
+        }
+    }
+
+    pub fn finish_rename(&mut self) {
+        let Some(browser) = self.browser.as_mut() else {
+            return;
+        };
+        let BrowseInput::Rename(title) = std::mem::replace(&mut browser.input, BrowseInput::None)
+        else {
+            return;
+        };
+        let title = title.trim().to_string();
+        if title.is_empty() {
+            return;
+        }
+        let Some(entry) = browser
+            .visible()
+            .get(browser.selected)
+            .map(|e| (e.path.clone(), e.id.clone()))
+        else {
+            return;
+        };
+        let (path, id) = entry;
+
+        match storage::rename_conversation(&path, &title) {
+            Ok(()) => {
+                if let Some(e) = browser.entries.iter_mut().find(|e| e.path == path) {
+                    e.title = title.clone();
+                }
+                if let Some(conv) = self
+                    .conversations
+                    .iter_mut()
+                    .find(|c| c.id.to_string() == id)
+                {
+                    conv.title = Some(title);
+                }
+            }
+            Err(e) => self.set_error(format!("Failed to rename: {}", e)),
+        }
+    }
+
+    pub fn delete_selected_conversation(&mut self) {
+        let Some(browser) = self.browser.as_mut() else {
+            return;
+        };
+        browser.input = BrowseInput::None;
+        let Some((path, id)) = browser
+            .selected_entry()
+            .map(|e| (e.path.clone(), e.id.clone()))
+        else {
+            return;
+        };
+
+        match storage::delete_conversation(&path) {
+            Ok(()) => {
+                browser.entries.retain(|e| e.path != path);
+                browser.clamp_selection();
+                self.status_message = Some("Conversation deleted".to_string());
+                if browser.entries.is_empty() {
+                    self.browser = None;
+                    self.mode = Mode::Normal;
+                }
+                // An open tab of it no longer has a file
+                if let Some(conv) = self
+                    .conversations
+                    .iter_mut()
+                    .find(|c| c.id.to_string() == id)
+                {
+                    conv.saved = false;
+                    self.save_session();
+                }
+            }
+            Err(e) => self.set_error(format!("Failed to delete: {}", e)),
+        }
+    }
+
+    /// Loads a saved conversation into a new tab, or switches to it if it is
+    /// already open.
+    pub fn open_conversation_file(&mut self, path: &Path) {
+        match storage::load_conversation(path) {
+            Ok(conv) => {
+                if let Some(index) = self.conversations.iter().position(|c| c.id == conv.id) {
+                    self.active_tab = index;
+                    self.save_session();
+                } else {
+                    self.status_message = Some(format!("Opened: {}", conv.display_title()));
+                    self.conversations.push(conv);
+                    self.active_tab = self.conversations.len() - 1;
+                    self.save_session();
+                }
+            }
+            Err(e) => {
+                self.set_error(format!("Failed to open: {}", e));
+            }
+        }
+    }
+
     pub fn toggle_help(&mut self) {
         self.mode = if self.mode == Mode::Help {
             Mode::Normal
@@ -106,51 +658,320 @@ impl App {
         };
     }
 
+    // `cursor_position` is a byte index into `input` that always sits on a
+    // grapheme boundary, so accents, CJK and emoji move and delete as one unit.
+
     pub fn move_cursor_left(&mut self) {
-        if self.cursor_position > 0 {
-            self.cursor_position -= 1;
-        }
+        self.cursor_position = text::prev_grapheme_boundary(&self.input, self.cursor_position);
     }
 
     pub fn move_cursor_right(&mut self) {
-        if self.cursor_position < self.input.len() {
-            self.cursor_position += 1;
-        }
+        self.cursor_position = text::next_grapheme_boundary(&self.input, self.cursor_position);
     }
 
     pub fn insert_char(&mut self, c: char) {
         self.input.insert(self.cursor_position, c);
-        self.cursor_position += 1;
+        self.cursor_position += c.len_utf8();
+    }
+
+    pub fn edit_text(&self, target: EditTarget) -> String {
+        match target {
+            EditTarget::Input => self.input.clone(),
+            EditTarget::SystemPrompt => self
+                .current_conversation()
+                .system_prompt
+                .clone()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Loads text back from the external editor.
+    pub fn apply_edit(&mut self, target: EditTarget, text: String) {
+        // Editors usually end the file with a newline
+        let text = text.trim_end_matches('\n').to_string();
+        match target {
+            EditTarget::Input => {
+                self.cursor_position = text.len();
+                self.input = text;
+                self.mode = Mode::Insert;
+            }
+            EditTarget::SystemPrompt => {
+                let prompt = text.trim();
+                self.current_conversation_mut().system_prompt =
+                    (!prompt.is_empty()).then(|| prompt.to_string());
+                self.status_message = Some(if prompt.is_empty() {
+                    "System prompt cleared".to_string()
+                } else {
+                    "System prompt updated".to_string()
+                });
+            }
+        }
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        self.input.insert_str(self.cursor_position, s);
+        self.cursor_position += s.len();
+    }
+
+    /// Inserts pasted text in one step. Newlines are kept as part of the
+    /// input rather than sending it, and long pastes are collapsed to a
+    /// placeholder that is expanded again on submit.
+    pub fn paste(&mut self, text: &str) {
+        if !matches!(self.mode, Mode::Normal | Mode::Insert) {
+            return;
+        }
+        self.mode = Mode::Insert;
+
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let line_count = text.lines().count();
+        if line_count <= PASTE_COLLAPSE_LINES {
+            self.insert_str(&text);
+            return;
+        }
+
+        let mut placeholder = format!("[pasted {} lines]", line_count);
+        let mut n = 1;
+        while self.pastes.iter().any(|(p, _)| *p == placeholder) {
+            n += 1;
+            placeholder = format!("[pasted {} lines ({})]", line_count, n);
+        }
+        self.insert_str(&placeholder);
+        self.pastes.push((placeholder, text));
+    }
+
+    /// The messages the next request would send if the input were
+    /// submitted now, with pastes expanded.
+    fn pending_messages(&self) -> Vec<Message> {
+        let mut messages = self.current_conversation().messages.clone();
+        let input = self.input.trim();
+        if (!input.is_empty() || !self.attachments.is_empty()) && !input.starts_with('/') {
+            let mut expanded = self.input.clone();
+            for (placeholder, text) in &self.pastes {
+                expanded = expanded.replacen(placeholder.as_str(), text, 1);
+            }
+            let mut message = Message::new(Role::User, expanded);
+            message.attachments = self.attachments.clone();
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Identifies what the next request would contain, to notice changes.
+    /// Hashing the history is cheap next to cloning it, which only happens
+    /// when this changes.
+    fn request_fingerprint(&self) -> u64 {
+        let conv = self.current_conversation();
+        let mut hasher = DefaultHasher::new();
+        conv.id.hash(&mut hasher);
+        for message in &conv.messages {
+            hash_message(message, &mut hasher);
+        }
+        conv.system_prompt.hash(&mut hasher);
+        self.input.hash(&mut hasher);
+        self.pastes.hash(&mut hasher);
+        for attachment in &self.attachments {
+            attachment.path.hash(&mut hasher);
+        }
+        self.pending_model_change
+            .as_ref()
+            .or(self.current_model.as_ref())
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Keeps `token_count` in step with the input and history. Returns a
+    /// count to run against the API once the input has settled.
+    pub fn poll_token_count(&mut self) -> Option<CountRequest> {
+        if self.is_loading() {
+            return None;
+        }
+        let fingerprint = self.request_fingerprint();
+        if self.token_count.as_ref().map(|c| c.fingerprint) != Some(fingerprint) {
+            let messages = self.pending_messages();
+            if messages.is_empty() {
+                self.token_count = None;
+                return None;
+            }
+            let system_prompt = self.current_conversation().system_prompt.as_deref();
+            self.token_count = Some(TokenCount {
+                fingerprint,
+                changed_at: Instant::now(),
+                requested: false,
+                tokens: api::estimate_tokens(&messages, system_prompt),
+                exact: false,
+            });
+            return None;
+        }
+
+        let count = self.token_count.as_mut()?;
+        if count.requested
+            || count.changed_at.elapsed() < TOKEN_COUNT_DELAY
+            || self.api_client.is_none()
+        {
+            return None;
+        }
+        count.requested = true;
+        Some(CountRequest {
+            fingerprint,
+            messages: self.pending_messages(),
+            system_prompt: self.current_conversation().system_prompt.clone(),
+            model: self
+                .pending_model_change
+                .clone()
+                .or_else(|| self.current_model.clone()),
+            tools: self.tools.definitions(),
+        })
+    }
+
+    /// Applies an exact count from the API. Counts for input that has since
+    /// changed are dropped, and on failure the local estimate stays.
+    pub fn set_token_count(&mut self, fingerprint: u64, tokens: u64) {
+        if let Some(count) = self.token_count.as_mut() {
+            if count.fingerprint == fingerprint {
+                count.tokens = tokens;
+                count.exact = true;
+            }
+        }
+    }
+
+    /// Share of the context window the next request would fill.
+    pub fn context_fraction(&self) -> Option<f64> {
+        let count = self.token_count.as_ref()?;
+        Some(count.tokens as f64 / self.config.context_window as f64)
+    }
+
+    /// Replaces paste placeholders still present in `input` with the text
+    /// they stand for.
+    fn expand_pastes(&mut self, input: String) -> String {
+        let mut expanded = input;
+        for (placeholder, text) in self.pastes.drain(..) {
+            expanded = expanded.replacen(&placeholder, &text, 1);
+        }
+        expanded
     }
 
     pub fn delete_char(&mut self) {
         if self.cursor_position > 0 {
-            self.cursor_position -= 1;
-            self.input.remove(self.cursor_position);
+            let start = text::prev_grapheme_boundary(&self.input, self.cursor_position);
+            self.input.replace_range(start..self.cursor_position, "");
+            self.cursor_position = start;
         }
     }
 
-    pub fn submit(&mut self) -> Option<String> {
-        if self.input.is_empty() {
+    pub fn delete_char_forward(&mut self) {
+        let end = text::next_grapheme_boundary(&self.input, self.cursor_position);
+        self.input.replace_range(self.cursor_position..end, "");
+    }
+
+    pub fn move_cursor_up(&mut self) {
+        self.cursor_position = text::line_up(&self.input, self.cursor_position);
+    }
+
+    pub fn move_cursor_down(&mut self) {
+        self.cursor_position = text::line_down(&self.input, self.cursor_position);
+    }
+
+    pub fn move_cursor_line_start(&mut self) {
+        self.cursor_position = text::line_start(&self.input, self.cursor_position);
+    }
+
+    pub fn move_cursor_line_end(&mut self) {
+        self.cursor_position = text::line_end(&self.input, self.cursor_position);
+    }
+
+    pub fn move_cursor_word_left(&mut self) {
+        self.cursor_position = text::word_start_before(&self.input, self.cursor_position);
+    }
+
+    pub fn move_cursor_word_right(&mut self) {
+        self.cursor_position = text::word_end_after(&self.input, self.cursor_position);
+    }
+
+    pub fn delete_word(&mut self) {
+        let start = text::word_start_before(&self.input, self.cursor_position);
+        self.input.replace_range(start..self.cursor_position, "");
+        self.cursor_position = start;
+    }
+
+    pub fn delete_word_forward(&mut self) {
+        let end = text::word_end_after(&self.input, self.cursor_position);
+        self.input.replace_range(self.cursor_position..end, "");
+    }
+
+    pub fn submit(&mut self) -> Option<RequestKind> {
+        if self.input.trim().is_empty() && self.attachments.is_empty() {
             return None;
         }
         let input = std::mem::take(&mut self.input);
+        let input = self.expand_pastes(input);
         self.cursor_position = 0;
+        self.input_scroll = 0;
 
         // Check for commands
         if input.starts_with('/') {
-            self.handle_command(&input);
-            return None;
+            return self.handle_command(&input);
         }
 
-        // Add user message
-        self.current_conversation_mut()
-            .add_message(Message::new(Role::User, input.clone()));
+        // Images mentioned as `@name.png` are attached too. If one can't be,
+        // the input is kept so it can be fixed.
+        for path in attachment::mentioned_images(&input) {
+            if let Err(e) = self.attach(path) {
+                self.set_error(e);
+                self.cursor_position = input.len();
+                self.input = input;
+                return None;
+            }
+        }
+
+        // Add user message and follow the reply as it streams in
+        let mut message = Message::new(Role::User, input);
+        message.attachments = std::mem::take(&mut self.attachments);
+        let conv = self.current_conversation_mut();
+        conv.add_message(message);
+        conv.follow_bottom = true;
 
-        Some(input)
+        Some(RequestKind::Reply)
+    }
+
+    /// Adds an image to the next message. Attaching the same file twice
+    /// does nothing; one that would make the request carry too much image
+    /// data is refused.
+    pub fn attach(&mut self, path: &str) -> Result<(), String> {
+        let attachment =
+            Attachment::load(&attachment::resolve_path(path)).map_err(|e| format!("{:#}", e))?;
+        if self.attachments.iter().any(|a| a.path == attachment.path) {
+            return Ok(());
+        }
+        let sent = self
+            .current_conversation()
+            .messages
+            .iter()
+            .flat_map(|m| &m.attachments);
+        attachment::check_limits(&attachment, &self.attachments, sent)
+            .map_err(|e| e.to_string())?;
+        self.attachments.push(attachment);
+        Ok(())
     }
 
-    fn handle_command(&mut self, input: &str) {
+    /// Readies the last assistant message to be continued.
+    fn prepare_continue(&mut self) -> Option<RequestKind> {
+        let conv = self.current_conversation_mut();
+        match conv.messages.last_mut() {
+            Some(last) if last.role == Role::Assistant && !last.content.trim().is_empty() => {
+                // The API rejects a final assistant turn that ends in whitespace
+                let len = last.content.trim_end().len();
+                last.content.truncate(len);
+                conv.follow_bottom = true;
+                Some(RequestKind::Continue)
+            }
+            _ => {
+                self.set_error("Nothing to continue: the last message is not a reply".to_string());
+                None
+            }
+        }
+    }
+
+    fn handle_command(&mut self, input: &str) -> Option<RequestKind> {
         let parts: Vec<&str> = input.splitn(2, ' ').collect();
         match parts[0] {
             "/model" => {
@@ -164,6 +985,128 @@ impl App {
                     self.status_message = Some(format!("Current model: {}", current));
                 }
             }
+            "/system" => {
+                let arg = parts.get(1).map(|a| a.trim()).unwrap_or("");
+                match arg {
+                    "" => {
+                        self.status_message =
+                            Some(match &self.current_conversation().system_prompt {
+                                Some(prompt) => {
+                                    format!("System prompt: {}", prompt.replace('\n', " "))
+                                }
+                                None => "No system prompt set".to_string(),
+                            });
+                    }
+                    "clear" => {
+                        self.current_conversation_mut().system_prompt = None;
+                        self.status_message = Some("System prompt cleared".to_string());
+                    }
+                    "edit" => {
+                        self.pending_edit = Some(EditTarget::SystemPrompt);
+                    }
+                    prompt => {
+                        self.current_conversation_mut().system_prompt = Some(prompt.to_string());
+                        self.status_message = Some("System prompt set".to_string());
+                    }
+                }
+            }
+            "/open" => {
+                self.open_browser();
+            }
+            "/config" => match parts.get(1).map(|a| a.trim()) {
+                Some("reload") => self.reload_config(),
+                _ => {
+                    self.status_message =
+                        Some(format!("Config file: {}", Config::path().display()));
+                }
+            },
+            "/continue" => return self.prepare_continue(),
+            "/tools" => match parts.get(1).map(|a| a.trim()) {
+                None | Some("") => {
+                    let names: Vec<String> = self
+                        .tools
+                        .definitions()
+                        .into_iter()
+                        .map(|t| t.name)
+                        .collect();
+                    self.status_message = Some(if names.is_empty() {
+                        "No tools available".to_string()
+                    } else {
+                        format!(
+                            "Tools: {}{}",
+                            names.join(", "),
+                            if self.dry_run { " (dry run)" } else { "" }
+                        )
+                    });
+                }
+                Some("dry-run") => {
+                    self.dry_run = !self.dry_run;
+                    self.status_message = Some(format!(
+                        "Dry run {}",
+                        if self.dry_run {
+                            "on: tool calls are not run"
+                        } else {
+                            "off"
+                        }
+                    ));
+                }
+                Some(other) => self.set_error(format!("Unknown /tools option: {}", other)),
+            },
+            "/think" => {
+                let arg = parts.get(1).map(|a| a.trim()).unwrap_or("");
+                let budget = match arg {
+                    "" if self.thinking_budget.is_some() => None,
+                    "" => Some(DEFAULT_THINKING_BUDGET),
+                    "off" => None,
+                    n => match n.parse::<u32>() {
+                        Ok(n) if n >= MIN_THINKING_BUDGET => Some(n),
+                        _ => {
+                            self.set_error(format!(
+                                "Thinking budget must be a number of tokens, at least {}",
+                                MIN_THINKING_BUDGET
+                            ));
+                            return None;
+                        }
+                    },
+                };
+                self.thinking_budget = budget;
+                self.status_message = Some(match budget {
+                    Some(n) => format!("Extended thinking on ({} token budget)", n),
+                    None => "Extended thinking off".to_string(),
+                });
+            }
+            "/attach" => match parts.get(1).map(|a| a.trim()).unwrap_or("") {
+                "" => {
+                    let names: Vec<String> =
+                        self.attachments.iter().map(Attachment::file_name).collect();
+                    self.status_message = Some(if names.is_empty() {
+                        "No attachments".to_string()
+                    } else {
+                        format!("Attached: {}", names.join(", "))
+                    });
+                }
+                "clear" => {
+                    self.attachments.clear();
+                    self.status_message = Some("Attachments removed".to_string());
+                }
+                path => match self.attach(path) {
+                    Ok(()) => {
+                        let names: Vec<String> =
+                            self.attachments.iter().map(Attachment::file_name).collect();
+                        self.status_message = Some(format!("Attached: {}", names.join(", ")));
+                    }
+                    Err(e) => self.set_error(e),
+                },
+            },
+            "/mcp" => {
+                let arg = parts.get(1).map(|a| a.trim()).unwrap_or("");
+                match arg.split_once(' ').unwrap_or((arg, "")) {
+                    ("", _) => self.open_mcp(),
+                    ("restart", "") => self.restart_mcp(None),
+                    ("restart", name) => self.restart_mcp(Some(name.trim())),
+                    (other, _) => self.set_error(format!("Unknown /mcp option: {}", other)),
+                }
+            }
             "/help" => {
                 self.mode = Mode::Help;
             }
@@ -171,26 +1114,414 @@ impl App {
                 self.set_error(format!("Unknown command: {}", parts[0]));
             }
         }
+        None
+    }
+
+    /// Pushes an empty assistant message onto a conversation and marks it
+    /// as loading.
+    pub fn start_assistant_message(&mut self, conversation_id: Uuid) {
+        let mut message = Message::new(Role::Assistant, String::new());
+        message.model = self.current_model.clone();
+        if let Some(conv) = self.conversation_mut(conversation_id) {
+            conv.add_message(message);
+            conv.is_loading = true;
+        }
+    }
+
+    pub fn schedule_retry(&mut self, conversation_id: Uuid, retry: Retry) {
+        self.retries
+            .insert(conversation_id, (Instant::now() + retry.delay, retry));
+    }
+
+    /// Marks a conversation as loading again so a continuation streams into
+    /// its last assistant message.
+    pub fn resume_assistant_message(&mut self, conversation_id: Uuid) {
+        let Some(conv) = self.conversation_mut(conversation_id) else {
+            return;
+        };
+        conv.is_loading = true;
+        let mut base = Usage::default();
+        if let Some(last) = conv.messages.last_mut() {
+            last.stop_reason = None;
+            last.interrupted = false;
+            base = last.usage.unwrap_or_default();
+        }
+        self.usage_base.insert(conversation_id, base);
+    }
+
+    /// The message a stream is writing to: the last one of a conversation
+    /// that is still loading.
+    fn streaming_message(&mut self, conversation_id: Uuid) -> Option<&mut Message> {
+        let conv = self.conversation_mut(conversation_id)?;
+        if !conv.is_loading {
+            return None;
+        }
+        conv.messages.last_mut()
+    }
+
+    pub fn start_thinking_block(&mut self, conversation_id: Uuid, block: ThinkingBlock) {
+        if let Some(message) = self.streaming_message(conversation_id) {
+            message.thinking.push(block);
+        }
+    }
+
+    pub fn append_thinking(&mut self, conversation_id: Uuid, text: &str) {
+        let Some(message) = self.streaming_message(conversation_id) else {
+            return;
+        };
+        if let Some(ThinkingBlock::Thinking { thinking, .. }) = message.thinking.last_mut() {
+            thinking.push_str(text);
+        }
+    }
+
+    pub fn set_thinking_signature(&mut self, conversation_id: Uuid, text: String) {
+        let Some(message) = self.streaming_message(conversation_id) else {
+            return;
+        };
+        if let Some(ThinkingBlock::Thinking { signature, .. }) = message.thinking.last_mut() {
+            *signature = text;
+        }
+    }
+
+    pub fn add_tool_call(&mut self, conversation_id: Uuid, call: ToolCall) {
+        if let Some(message) = self.streaming_message(conversation_id) {
+            message.tool_calls.push(call);
+        }
+    }
+
+    pub fn set_tool_result(
+        &mut self,
+        conversation_id: Uuid,
+        tool_use_id: &str,
+        result: ToolResult,
+    ) {
+        let Some(message) = self.streaming_message(conversation_id) else {
+            return;
+        };
+        if let Some(call) = message.tool_calls.iter_mut().find(|c| c.id == tool_use_id) {
+            call.result = Some(result);
+        }
+    }
+
+    fn tool_allowed(&self, conversation_id: Uuid, name: &str) -> bool {
+        self.config
+            .tools
+            .allow
+            .iter()
+            .any(|allowed| allowed == name)
+            || self
+                .conversation(conversation_id)
+                .is_some_and(|c| c.allowed_tools.contains(name))
+    }
+
+    /// Takes the next queued tool call of a conversation. Calls that aren't
+    /// allowed yet wait for approval; in dry-run mode none are run.
+    pub fn next_tool_step(&mut self, conversation_id: Uuid) -> ToolStep {
+        while let Some(call) = self
+            .tool_queue
+            .get_mut(&conversation_id)
+            .and_then(|queue| queue.pop_front())
+        {
+            if self.dry_run {
+                let result = ToolResult {
+                    content: DRY_RUN_RESULT.to_string(),
+                    is_error: false,
+                };
+                self.set_tool_result(conversation_id, &call.id, result);
+                continue;
+            }
+            if self.tool_allowed(conversation_id, &call.name) {
+                return ToolStep::Run(call);
+            }
+            // The prompt takes focus as soon as a call needs it; keys typed
+            // just before it appeared are swallowed by the guard
+            if self.mode != Mode::Approve {
+                self.mode_before_approval = self.mode;
+                self.mode = Mode::Approve;
+                self.approval_shown = Instant::now();
+            }
+            self.approvals.push_back(Approval {
+                conversation_id,
+                call,
+            });
+            return ToolStep::Wait;
+        }
+
+        self.tool_queue.remove(&conversation_id);
+        let done = self
+            .conversation(conversation_id)
+            .filter(|c| c.is_loading)
+            .and_then(|c| c.messages.last())
+            .is_some_and(|m| m.pending_tool_calls().next().is_none());
+        if !done {
+            return ToolStep::Wait;
+        }
+        let max_rounds = self.config.tools.max_rounds as usize;
+        if self.tool_rounds(conversation_id) > max_rounds {
+            self.stop_loading(conversation_id);
+            self.autosave(conversation_id);
+            self.status_message = Some(format!(
+                "Stopped after {} rounds of tool calls; send a message to let the model go on",
+                max_rounds
+            ));
+            return ToolStep::Wait;
+        }
+        ToolStep::Done
+    }
+
+    /// Replies with tool calls since the user's last message.
+    fn tool_rounds(&self, conversation_id: Uuid) -> usize {
+        self.conversation(conversation_id).map_or(0, |conv| {
+            conv.messages
+                .iter()
+                .rev()
+                .take_while(|m| m.role != Role::User)
+                .filter(|m| !m.tool_calls.is_empty())
+                .count()
+        })
     }
 
-    pub fn start_assistant_message(&mut self) {
-        self.current_conversation_mut()
-            .add_message(Message::new(Role::Assistant, String::new()));
+    /// Whether the approval prompt has been up long enough to take answers.
+    pub fn approval_ready(&self) -> bool {
+        self.approval_shown.elapsed() >= APPROVAL_GUARD
+    }
+
+    /// Answers the oldest approval prompt. Returns the conversation and what
+    /// to do next with its tool calls.
+    pub fn answer_approval(&mut self, choice: ApprovalChoice) -> Option<(Uuid, ToolStep)> {
+        let Approval {
+            conversation_id,
+            call,
+        } = self.approvals.pop_front()?;
+        if self.approvals.is_empty() {
+            self.mode = self.mode_before_approval;
+        }
+        self.approval_shown = Instant::now();
+
+        let step = match choice {
+            ApprovalChoice::Once => ToolStep::Run(call),
+            ApprovalChoice::Always => {
+                if let Some(conv) = self.conversation_mut(conversation_id) {
+                    conv.allowed_tools.insert(call.name.clone());
+                }
+                ToolStep::Run(call)
+            }
+            ApprovalChoice::Deny => {
+                let result = ToolResult {
+                    content: DENIED_RESULT.to_string(),
+                    is_error: true,
+                };
+                self.set_tool_result(conversation_id, &call.id, result);
+                self.next_tool_step(conversation_id)
+            }
+        };
+        Some((conversation_id, step))
     }
 
-    pub fn append_to_last_message(&mut self, text: &str) {
-        if let Some(last) = self.current_conversation_mut().messages.last_mut() {
-            last.content.push_str(text);
+    /// Forgets the queued and unapproved tool calls of a conversation.
+    fn drop_tool_calls(&mut self, conversation_id: Uuid) {
+        self.tool_queue.remove(&conversation_id);
+        self.approvals
+            .retain(|a| a.conversation_id != conversation_id);
+        if self.mode == Mode::Approve && self.approvals.is_empty() {
+            self.mode = self.mode_before_approval;
         }
     }
 
-    pub fn finish_streaming(&mut self) {
-        self.is_loading = false;
+    pub fn set_stop_reason(&mut self, conversation_id: Uuid, reason: String) {
+        if let Some(conv) = self.conversation_mut(conversation_id) {
+            if !conv.is_loading {
+                return;
+            }
+            if let Some(last) = conv.messages.last_mut() {
+                last.stop_reason = Some(reason);
+            }
+        }
+    }
+
+    pub fn append_to_message(&mut self, conversation_id: Uuid, text: &str) {
+        if let Some(conv) = self.conversation_mut(conversation_id) {
+            if !conv.is_loading {
+                return;
+            }
+            if let Some(last) = conv.messages.last_mut() {
+                last.content.push_str(text);
+            }
+        }
+    }
+
+    /// Records the usage reported so far for the reply being streamed. The
+    /// session totals grow by the difference, so usage of a reply that is
+    /// later cancelled still counts.
+    pub fn update_usage(&mut self, conversation_id: Uuid, usage: Usage) {
+        let mut usage = usage;
+        if let Some(base) = self.usage_base.get(&conversation_id) {
+            usage += *base;
+        }
+        let Some(conv) = self.conversation_mut(conversation_id) else {
+            return;
+        };
+        if !conv.is_loading {
+            return;
+        }
+        let Some(last) = conv.messages.last_mut() else {
+            return;
+        };
+        let previous = last.usage.replace(usage).unwrap_or_default();
+        let model = last.model.clone();
+
+        self.session_usage += usage - previous;
+        if let Some(price) = model.and_then(|m| self.config.price(&m)) {
+            self.session_cost += price.cost(&usage) - price.cost(&previous);
+        }
+    }
+
+    /// Estimated cost of a conversation's replies, or `None` when no reply
+    /// has a known price.
+    pub fn conversation_cost(&self, conv: &Conversation) -> Option<f64> {
+        let costs: Vec<f64> = conv
+            .messages
+            .iter()
+            .filter_map(|m| {
+                let price = self.config.price(m.model.as_deref()?)?;
+                Some(price.cost(&m.usage?))
+            })
+            .collect();
+        (!costs.is_empty()).then(|| costs.iter().sum())
+    }
+
+    /// Numbers a new request or tool run for a conversation.
+    pub fn new_request_id(&mut self, conversation_id: Uuid) -> RequestId {
+        self.next_request += 1;
+        RequestId {
+            conversation_id,
+            generation: self.next_request,
+        }
+    }
+
+    /// Records the task running a request, replacing the previous one.
+    pub fn track_request(&mut self, id: RequestId, handle: AbortHandle) {
+        self.requests
+            .insert(id.conversation_id, (id.generation, handle));
+    }
+
+    /// Whether events tagged with `id` come from the conversation's running
+    /// request rather than one that was cancelled or replaced.
+    pub fn is_current_request(&self, id: RequestId) -> bool {
+        self.requests
+            .get(&id.conversation_id)
+            .is_some_and(|(generation, _)| *generation == id.generation)
+    }
+
+    /// Ends a completed reply. If the model asked for tools, the
+    /// conversation stays loading, the calls are queued and true is
+    /// returned.
+    pub fn finish_streaming(&mut self, conversation_id: Uuid) -> bool {
+        let calls: VecDeque<ToolCall> = self
+            .conversation(conversation_id)
+            .filter(|c| c.is_loading)
+            .and_then(|c| c.messages.last())
+            .map(|m| m.pending_tool_calls().cloned().collect())
+            .unwrap_or_default();
+        let has_calls = !calls.is_empty();
+        if has_calls {
+            self.requests.remove(&conversation_id);
+            self.usage_base.remove(&conversation_id);
+            self.retries.remove(&conversation_id);
+            self.tool_queue.insert(conversation_id, calls);
+        } else {
+            self.stop_loading(conversation_id);
+        }
+        self.autosave(conversation_id);
+        has_calls
+    }
+
+    /// Ends a request that failed. As with cancelling, an empty reply is
+    /// dropped and a partial one is kept and flagged as interrupted.
+    pub fn fail_streaming(&mut self, conversation_id: Uuid, error: ApiError) {
+        if let Some(conv) = self.conversation_mut(conversation_id) {
+            if conv.is_loading {
+                conv.drop_or_interrupt_reply();
+            }
+        }
+        self.stop_loading(conversation_id);
+        self.set_error(self.describe_api_error(&error));
+    }
+
+    fn stop_loading(&mut self, conversation_id: Uuid) {
+        self.requests.remove(&conversation_id);
+        self.usage_base.remove(&conversation_id);
+        self.retries.remove(&conversation_id);
+        if let Some(conv) = self.conversation_mut(conversation_id) {
+            conv.is_loading = false;
+        }
+    }
+
+    /// Saves a conversation after a completed turn and refreshes the session
+    /// file. Failures are reported but never interrupt the chat.
+    fn autosave(&mut self, conversation_id: Uuid) {
+        let Some(conv) = self.conversation_mut(conversation_id) else {
+            return;
+        };
+        if let Err(e) = storage::save_conversation(conv) {
+            self.set_error(format!("Autosave failed: {}", e));
+            return;
+        }
+        conv.saved = true;
+        self.save_session();
+    }
+
+    /// Writes the session file listing every open conversation that has been
+    /// saved to disk. Tabs without a file yet are left out.
+    pub fn save_session(&mut self) {
+        let saved: Vec<&Conversation> = self.conversations.iter().filter(|c| c.saved).collect();
+        let active = self.conversations.get(self.active_tab).filter(|c| c.saved);
+
+        if let Err(e) = storage::save_session(&saved, active) {
+            self.set_error(format!("Failed to save session: {}", e));
+        }
+    }
+
+    /// Saves every non-empty conversation and the session before exiting.
+    pub fn save_all(&mut self) {
+        for conv in self
+            .conversations
+            .iter_mut()
+            .filter(|c| !c.messages.is_empty())
+        {
+            if storage::save_conversation(conv).is_ok() {
+                conv.saved = true;
+            }
+        }
+        self.save_session();
+    }
+
+    /// Aborts the in-flight request of the active conversation. Whatever was
+    /// streamed so far is kept and flagged as interrupted; an empty reply is
+    /// dropped so the next request doesn't carry a blank assistant turn.
+    pub fn cancel_request(&mut self) {
+        let conv = self.current_conversation_mut();
+        if !conv.is_loading {
+            return;
+        }
+        conv.is_loading = false;
+        conv.drop_or_interrupt_reply();
+        let id = conv.id;
+
+        if let Some((_, handle)) = self.requests.remove(&id) {
+            handle.abort();
+        }
+        self.retries.remove(&id);
+        self.drop_tool_calls(id);
+        self.status_message = Some("Request cancelled".to_string());
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.current_conversation().is_loading
     }
 
     pub fn set_error(&mut self, error: String) {
         self.error_message = Some(error);
-        self.is_loading = false;
     }
 
     pub fn clear_error(&mut self) {
@@ -201,4 +1532,49 @@ impl App {
     pub fn has_api_key(&self) -> bool {
         self.api_client.is_some()
     }
+
+    /// An error message that says what went wrong and what to do about it.
+    fn describe_api_error(&self, error: &ApiError) -> String {
+        match error {
+            ApiError::InvalidRequest(m) => format!("Invalid request: {}", m),
+            ApiError::Authentication(m) => {
+                format!("Authentication failed ({}). Check ANTHROPIC_API_KEY.", m)
+            }
+            ApiError::Permission(m) => format!(
+                "Permission denied ({}). Your API key may not have access to this model.",
+                m
+            ),
+            ApiError::NotFound(m) => format!("Not found ({}). Check the model name with /model.", m),
+            ApiError::RequestTooLarge(_) => format!(
+                "Request too large. Start a new conversation ({}) or shorten your message.",
+                self.config.keys.new_conversation
+            ),
+            ApiError::RateLimit(m) => format!(
+                "Rate limited ({}). Wait a moment before sending again, or raise retry.max_retries.",
+                m
+            ),
+            ApiError::Overloaded(_) => "The API is overloaded right now. Try again shortly.".to_string(),
+            ApiError::Api(m) => format!("The API hit an internal error ({}). Try again.", m),
+            ApiError::Network(m) => format!(
+                "Could not reach the API ({}). Check your connection and api_base_url.",
+                m
+            ),
+            ApiError::Unexpected { .. } => error.to_string(),
+        }
+    }
+}
+
+/// Hashes the parts of a message that go into a request.
+fn hash_message(message: &Message, hasher: &mut impl Hasher) {
+    message.role.hash(hasher);
+    message.content.hash(hasher);
+    message.thinking.hash(hasher);
+    for call in &message.tool_calls {
+        // A call's input is fixed once it arrives, so its id stands in for it
+        call.id.hash(hasher);
+        call.result.hash(hasher);
+    }
+    for attachment in &message.attachments {
+        attachment.path.hash(hasher);
+    }
 }
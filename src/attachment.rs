@@ -0,0 +1,311 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+
+/// Largest image the API accepts, before encoding.
+pub const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+/// Most images a single message may carry.
+pub const MAX_ATTACHMENTS: usize = 20;
+/// Most base64 image data one request may carry. Every image in the history
+/// is sent again each turn, and the API refuses requests over 32 MB.
+pub const MAX_ENCODED_BYTES: usize = 24 * 1024 * 1024;
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "gif", "webp"];
+
+/// An image sent with a user message. Saved conversations keep only the
+/// path, and the file is read again when they are loaded.
+#[derive(Clone)]
+pub struct Attachment {
+    pub path: PathBuf,
+    pub media_type: String,
+    pub size: u64,
+    /// The file in base64, or `None` if it could not be read back.
+    pub data: Option<Arc<str>>,
+}
+
+impl Attachment {
+    /// Reads and encodes an image, refusing other files and files over the
+    /// size limit.
+    pub fn load(path: &Path) -> Result<Self> {
+        // Stored absolute so saved conversations find it from anywhere
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("{}: not found", path.display()))?;
+        let size = fs::metadata(&path)?.len();
+        if size > MAX_IMAGE_BYTES {
+            bail!(
+                "{}: larger than the {} limit",
+                path.display(),
+                format_size(MAX_IMAGE_BYTES)
+            );
+        }
+        let bytes =
+            fs::read(&path).with_context(|| format!("{}: could not read", path.display()))?;
+        let Some(media_type) = media_type(&bytes) else {
+            bail!("{}: not a PNG, JPEG, GIF or WebP image", path.display());
+        };
+        Ok(Self {
+            path,
+            media_type: media_type.to_string(),
+            size: bytes.len() as u64,
+            data: Some(
+                base64::engine::general_purpose::STANDARD
+                    .encode(&bytes)
+                    .into(),
+            ),
+        })
+    }
+
+    /// Rebuilds a saved attachment from its file. One that is gone or no
+    /// longer a valid image comes back without data.
+    pub fn restore(path: PathBuf, media_type: String, size: u64) -> Self {
+        Self::load(&path).unwrap_or(Self {
+            path,
+            media_type,
+            size,
+            data: None,
+        })
+    }
+
+    /// Bytes the image adds to a request.
+    pub fn encoded_len(&self) -> usize {
+        self.data.as_ref().map_or(0, |data| data.len())
+    }
+
+    pub fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.path.display().to_string())
+    }
+}
+
+/// Checks that `new` may join the images waiting to be sent (`pending`),
+/// given the images already `sent` earlier in the conversation.
+pub fn check_limits<'a>(
+    new: &Attachment,
+    pending: &'a [Attachment],
+    sent: impl IntoIterator<Item = &'a Attachment>,
+) -> Result<()> {
+    if pending.len() >= MAX_ATTACHMENTS {
+        bail!("A message can carry at most {} images", MAX_ATTACHMENTS);
+    }
+    let used: usize = sent
+        .into_iter()
+        .chain(pending.iter())
+        .map(Attachment::encoded_len)
+        .sum();
+    if used + new.encoded_len() > MAX_ENCODED_BYTES {
+        bail!(
+            "{}: the conversation's images would exceed the {} a request can carry",
+            new.file_name(),
+            format_size(MAX_ENCODED_BYTES as u64)
+        );
+    }
+    Ok(())
+}
+
+/// Recognises the supported formats by their leading bytes.
+fn media_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// Resolves a path typed by the user. `~/` is the home directory and
+/// relative paths start at the working directory.
+pub fn resolve_path(input: &str) -> PathBuf {
+    match input.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None => PathBuf::from(input),
+    }
+}
+
+/// Image paths written as `@name.png` in a message. Punctuation right after
+/// the name is not part of it.
+pub fn mentioned_images(text: &str) -> Vec<&str> {
+    text.split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|path| path.trim_end_matches(['.', ',', ';', ':', '!', '?', ')']))
+        .filter(|path| {
+            Path::new(path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        })
+        .collect()
+}
+
+/// File sizes as shown to the user: 812 B, 240 KB, 1.3 MB.
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{} KB", bytes / 1024),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nrest";
+
+    /// Runs `test` in a fresh directory, removed afterwards.
+    fn in_temp_dir(test: impl FnOnce(&Path)) {
+        let dir = std::env::temp_dir().join(format!("claude-tui-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| test(&dir)));
+        let _ = fs::remove_dir_all(&dir);
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    /// An attachment whose base64 data is `encoded_len` bytes long.
+    fn encoded(name: &str, encoded_len: usize) -> Attachment {
+        Attachment {
+            path: name.into(),
+            media_type: "image/png".to_string(),
+            size: encoded_len as u64 / 4 * 3,
+            data: Some("A".repeat(encoded_len).into()),
+        }
+    }
+
+    #[test]
+    fn recognises_images_by_their_leading_bytes() {
+        assert_eq!(media_type(PNG), Some("image/png"));
+        assert_eq!(media_type(&[0xff, 0xd8, 0xff, 0xe0]), Some("image/jpeg"));
+        assert_eq!(media_type(b"GIF87a..."), Some("image/gif"));
+        assert_eq!(media_type(b"GIF89a..."), Some("image/gif"));
+        assert_eq!(media_type(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(media_type(b"RIFF\0\0\0\0WAVEfmt "), None);
+        assert_eq!(media_type(b"RIFF"), None);
+        assert_eq!(media_type(b"plain text"), None);
+        assert_eq!(media_type(b""), None);
+    }
+
+    #[test]
+    fn loads_and_encodes_images() {
+        in_temp_dir(|dir| {
+            let path = dir.join("pixel.png");
+            fs::write(&path, PNG).unwrap();
+            let attachment = Attachment::load(&path).unwrap();
+            assert_eq!(attachment.path, path.canonicalize().unwrap());
+            assert_eq!(attachment.media_type, "image/png");
+            assert_eq!(attachment.size, PNG.len() as u64);
+            assert_eq!(attachment.data.as_deref(), Some("iVBORw0KGgpyZXN0"));
+            assert_eq!(attachment.encoded_len(), 16);
+            assert_eq!(attachment.file_name(), "pixel.png");
+        });
+    }
+
+    #[test]
+    fn refuses_missing_large_and_non_image_files() {
+        in_temp_dir(|dir| {
+            let error = Attachment::load(&dir.join("missing.png")).err().unwrap();
+            assert!(error.to_string().ends_with("missing.png: not found"));
+
+            let text = dir.join("notes.png");
+            fs::write(&text, "not an image").unwrap();
+            let error = Attachment::load(&text).err().unwrap();
+            assert!(error
+                .to_string()
+                .ends_with("not a PNG, JPEG, GIF or WebP image"));
+
+            let large = dir.join("large.png");
+            let file = fs::File::create(&large).unwrap();
+            file.set_len(MAX_IMAGE_BYTES + 1).unwrap();
+            let error = Attachment::load(&large).err().unwrap();
+            assert!(error.to_string().ends_with("larger than the 5.0 MB limit"));
+        });
+    }
+
+    #[test]
+    fn restores_missing_images_without_data() {
+        let attachment =
+            Attachment::restore("/no/such/image.png".into(), "image/png".to_string(), 42);
+        assert_eq!(attachment.path, Path::new("/no/such/image.png"));
+        assert_eq!(attachment.size, 42);
+        assert!(attachment.data.is_none());
+        assert_eq!(attachment.encoded_len(), 0);
+    }
+
+    #[test]
+    fn finds_mentioned_images() {
+        assert_eq!(
+            mentioned_images("Compare @a.png, @shots/b.JPG! and (@~/c.webp)"),
+            ["a.png", "shots/b.JPG"]
+        );
+        assert_eq!(mentioned_images("See @~/d.gif)."), ["~/d.gif"]);
+        assert!(mentioned_images("mail me@x.png or read @notes.txt and @").is_empty());
+    }
+
+    #[test]
+    fn resolves_home_relative_paths() {
+        assert_eq!(
+            resolve_path("~/pics/a.png"),
+            dirs::home_dir().unwrap_or_default().join("pics/a.png")
+        );
+        assert_eq!(resolve_path("pics/a.png"), Path::new("pics/a.png"));
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(812), "812 B");
+        assert_eq!(format_size(240 * 1024), "240 KB");
+        assert_eq!(format_size(1_363_149), "1.3 MB");
+    }
+
+    #[test]
+    fn limits_the_images_per_message() {
+        let pending: Vec<_> = (0..MAX_ATTACHMENTS)
+            .map(|i| encoded(&format!("{}.png", i), 10))
+            .collect();
+        let new = encoded("one-more.png", 10);
+        assert!(check_limits(&new, &pending[1..], []).is_ok());
+        let error = check_limits(&new, &pending, []).err().unwrap();
+        assert_eq!(error.to_string(), "A message can carry at most 20 images");
+    }
+
+    #[test]
+    fn limits_the_images_per_request() {
+        const MB: usize = 1024 * 1024;
+        let sent = [
+            encoded("first.png", 10 * MB),
+            encoded("second.png", 10 * MB),
+        ];
+        let pending = [encoded("third.png", 3 * MB)];
+
+        // Exactly at the cap
+        assert!(check_limits(&encoded("fits.png", MB), &pending, &sent).is_ok());
+
+        let error = check_limits(&encoded("over.png", MB + 1), &pending, &sent)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "over.png: the conversation's images would exceed the 24.0 MB a request can carry"
+        );
+
+        // Images that could not be read back are not sent, so they don't count
+        let gone = [Attachment::restore(
+            "/gone.png".into(),
+            "image/png".to_string(),
+            0,
+        )];
+        assert!(check_limits(&encoded("big.png", 24 * MB), &[], &gone).is_ok());
+        assert!(check_limits(&encoded("too-big.png", 24 * MB + 1), &[], &gone).is_err());
+    }
+}
@@ -0,0 +1,503 @@
+//! A stand-in for the Anthropic Messages API, for running the app and its
+//! tests offline.
+//!
+//! ```text
+//! mock_server [--port PORT] [--log FILE] [SCRIPT.json]
+//! ```
+//!
+//! Prints `listening on http://127.0.0.1:PORT` once it accepts connections;
+//! point the app at it with `ANTHROPIC_BASE_URL`. Without a script every
+//! request gets a streamed echo of the last user message. A script is a JSON
+//! array of replies served in order, the last one repeating:
+//!
+//! ```json
+//! [
+//!   { "text": "Hello!", "chunk_delay_ms": 50 },
+//!   { "thinking": "Let me see...", "text": "42" },
+//!   { "tool_use": [{ "name": "read_file", "input": { "path": "README.md" } }], "stop_reason": "tool_use" },
+//!   { "status": 529, "headers": { "retry-after": "1" } },
+//!   { "delay_ms": 5000, "text": "slow" },
+//!   { "events": [{ "event": "error", "data": { "type": "error", "error": { "type": "overloaded_error", "message": "Overloaded" } } }] }
+//! ]
+//! ```
+//!
+//! `/v1/messages/count_tokens` is answered with an estimate and is neither
+//! scripted nor logged. With `--log`, each Messages request body is appended
+//! to FILE as one JSON line.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+/// One scripted reply.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Reply {
+    status: u16,
+    headers: HashMap<String, String>,
+    /// Wait before sending anything.
+    delay_ms: u64,
+    /// Reply text, streamed as `text_delta` events. Defaults to an echo.
+    text: Option<String>,
+    /// Thinking streamed as a `thinking` block before the text.
+    thinking: Option<String>,
+    /// Tool calls after the text, their input streamed as `input_json_delta`.
+    tool_use: Vec<ScriptedToolUse>,
+    /// Characters per `text_delta`.
+    chunk_size: usize,
+    chunk_delay_ms: u64,
+    stop_reason: String,
+    /// Raw SSE events sent instead of the generated stream.
+    events: Vec<ScriptedEvent>,
+    /// Response body for errors and non-streaming requests.
+    body: Option<Value>,
+}
+
+impl Default for Reply {
+    fn default() -> Self {
+        Self {
+            status: 200,
+            headers: HashMap::new(),
+            delay_ms: 0,
+            text: None,
+            thinking: None,
+            tool_use: Vec::new(),
+            chunk_size: 8,
+            chunk_delay_ms: 20,
+            stop_reason: "end_turn".to_string(),
+            events: Vec::new(),
+            body: None,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptedToolUse {
+    name: String,
+    #[serde(default)]
+    input: Value,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptedEvent {
+    event: String,
+    data: Value,
+    #[serde(default)]
+    delay_ms: u64,
+}
+
+struct Server {
+    script: Vec<Reply>,
+    next: AtomicUsize,
+    log: Option<Mutex<tokio::fs::File>>,
+}
+
+impl Server {
+    fn next_reply(&self) -> Reply {
+        if self.script.is_empty() {
+            return Reply::default();
+        }
+        let i = self.next.fetch_add(1, Ordering::Relaxed);
+        self.script[i.min(self.script.len() - 1)].clone()
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut port = 0;
+    let mut log = None;
+    let mut script = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .ok_or_else(|| anyhow!("--port needs a number"))?;
+            }
+            "--log" => log = Some(args.next().ok_or_else(|| anyhow!("--log needs a file"))?),
+            path => {
+                let content =
+                    std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?;
+                script =
+                    serde_json::from_str(&content).with_context(|| format!("parsing {}", path))?;
+            }
+        }
+    }
+
+    let log = match log {
+        Some(path) => Some(Mutex::new(
+            tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+                .with_context(|| format!("opening {}", path))?,
+        )),
+        None => None,
+    };
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    println!("listening on http://{}", listener.local_addr()?);
+
+    let server = Arc::new(Server {
+        script,
+        next: AtomicUsize::new(0),
+        log,
+    });
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(&server, stream).await {
+                eprintln!("mock_server: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(server: &Server, stream: TcpStream) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let (method, path, body) = read_request(&mut stream).await?;
+    let stream = stream.get_mut();
+
+    if method == "POST" && path == "/v1/messages/count_tokens" {
+        // Roughly four bytes per token, like the app's own estimate
+        let count = json!({ "input_tokens": body.len() / 4 });
+        return write_json(stream, 200, &HashMap::new(), &count).await;
+    }
+
+    if let Some(log) = &server.log {
+        let line = serde_json::from_slice::<Value>(&body)
+            .map(|v| v.to_string())
+            .unwrap_or_else(|_| String::from_utf8_lossy(&body).into_owned());
+        let mut log = log.lock().await;
+        log.write_all(format!("{}\n", line).as_bytes()).await?;
+        log.flush().await?;
+    }
+
+    if method != "POST" || path != "/v1/messages" {
+        let error = error_body("not_found_error", &format!("{} {} not found", method, path));
+        return write_json(stream, 404, &HashMap::new(), &error).await;
+    }
+
+    let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    let reply = server.next_reply();
+    tokio::time::sleep(Duration::from_millis(reply.delay_ms)).await;
+
+    if reply.status != 200 {
+        let body = reply
+            .body
+            .clone()
+            .unwrap_or_else(|| default_error(reply.status));
+        return write_json(stream, reply.status, &reply.headers, &body).await;
+    }
+
+    let text = reply.text.clone().unwrap_or_else(|| echo(&request));
+    let model = request["model"].as_str().unwrap_or("mock").to_string();
+    let input_tokens = body.len() / 4;
+    let output_tokens = (text.chars().count() / 4).max(1);
+
+    if !request["stream"].as_bool().unwrap_or(false) {
+        let body = reply.body.clone().unwrap_or_else(|| {
+            let mut content = Vec::new();
+            if let Some(thinking) = &reply.thinking {
+                content.push(json!({ "type": "thinking", "thinking": thinking, "signature": "mock-signature" }));
+            }
+            content.push(json!({ "type": "text", "text": text }));
+            for (i, tool) in reply.tool_use.iter().enumerate() {
+                content.push(json!({ "type": "tool_use", "id": tool_use_id(i), "name": tool.name, "input": tool.input }));
+            }
+            json!({
+                "id": "msg_mock",
+                "type": "message",
+                "role": "assistant",
+                "model": model,
+                "content": content,
+                "stop_reason": reply.stop_reason,
+                "stop_sequence": null,
+                "usage": { "input_tokens": input_tokens, "output_tokens": output_tokens },
+            })
+        });
+        return write_json(stream, 200, &reply.headers, &body).await;
+    }
+
+    let mut head = String::from("HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ncache-control: no-cache\r\nconnection: close\r\n");
+    for (name, value) in &reply.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+
+    let events = if reply.events.is_empty() {
+        generated_events(&reply, &model, &text, input_tokens, output_tokens)
+    } else {
+        reply.events.clone()
+    };
+    for event in events {
+        tokio::time::sleep(Duration::from_millis(event.delay_ms)).await;
+        let frame = format!("event: {}\ndata: {}\n\n", event.event, event.data);
+        stream.write_all(frame.as_bytes()).await?;
+        stream.flush().await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// The event sequence the real API sends for a text reply, with an optional
+/// thinking block first and tool calls last.
+fn generated_events(
+    reply: &Reply,
+    model: &str,
+    text: &str,
+    input_tokens: usize,
+    output_tokens: usize,
+) -> Vec<ScriptedEvent> {
+    let event = |name: &str, data: Value, delay_ms: u64| ScriptedEvent {
+        event: name.to_string(),
+        data,
+        delay_ms,
+    };
+
+    let mut events = vec![
+        event(
+            "message_start",
+            json!({
+                "type": "message_start",
+                "message": {
+                    "id": "msg_mock",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": model,
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": { "input_tokens": input_tokens, "output_tokens": 1 },
+                },
+            }),
+            0,
+        ),
+        event("ping", json!({ "type": "ping" }), 0),
+    ];
+
+    let mut index = 0;
+    if let Some(thinking) = &reply.thinking {
+        events.push(event(
+            "content_block_start",
+            json!({ "type": "content_block_start", "index": index, "content_block": { "type": "thinking", "thinking": "" } }),
+            0,
+        ));
+        let chars: Vec<char> = thinking.chars().collect();
+        for chunk in chars.chunks(reply.chunk_size.max(1)) {
+            let chunk: String = chunk.iter().collect();
+            events.push(event(
+                "content_block_delta",
+                json!({ "type": "content_block_delta", "index": index, "delta": { "type": "thinking_delta", "thinking": chunk } }),
+                reply.chunk_delay_ms,
+            ));
+        }
+        events.push(event(
+            "content_block_delta",
+            json!({ "type": "content_block_delta", "index": index, "delta": { "type": "signature_delta", "signature": "mock-signature" } }),
+            0,
+        ));
+        events.push(event(
+            "content_block_stop",
+            json!({ "type": "content_block_stop", "index": index }),
+            0,
+        ));
+        index += 1;
+    }
+
+    events.push(event(
+        "content_block_start",
+        json!({ "type": "content_block_start", "index": index, "content_block": { "type": "text", "text": "" } }),
+        0,
+    ));
+    let chars: Vec<char> = text.chars().collect();
+    for chunk in chars.chunks(reply.chunk_size.max(1)) {
+        let chunk: String = chunk.iter().collect();
+        events.push(event(
+            "content_block_delta",
+            json!({ "type": "content_block_delta", "index": index, "delta": { "type": "text_delta", "text": chunk } }),
+            reply.chunk_delay_ms,
+        ));
+    }
+
+    events.push(event(
+        "content_block_stop",
+        json!({ "type": "content_block_stop", "index": index }),
+        0,
+    ));
+
+    for (i, tool) in reply.tool_use.iter().enumerate() {
+        index += 1;
+        events.push(event(
+            "content_block_start",
+            json!({
+                "type": "content_block_start",
+                "index": index,
+                "content_block": { "type": "tool_use", "id": tool_use_id(i), "name": tool.name, "input": {} },
+            }),
+            0,
+        ));
+        let input: Vec<char> = tool.input.to_string().chars().collect();
+        for chunk in input.chunks(reply.chunk_size.max(1)) {
+            let chunk: String = chunk.iter().collect();
+            events.push(event(
+                "content_block_delta",
+                json!({ "type": "content_block_delta", "index": index, "delta": { "type": "input_json_delta", "partial_json": chunk } }),
+                reply.chunk_delay_ms,
+            ));
+        }
+        events.push(event(
+            "content_block_stop",
+            json!({ "type": "content_block_stop", "index": index }),
+            0,
+        ));
+    }
+
+    events.extend([
+        event(
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": reply.stop_reason, "stop_sequence": null },
+                "usage": { "output_tokens": output_tokens },
+            }),
+            0,
+        ),
+        event("message_stop", json!({ "type": "message_stop" }), 0),
+    ]);
+    events
+}
+
+fn tool_use_id(i: usize) -> String {
+    format!("toolu_mock_{}", i)
+}
+
+fn echo(request: &Value) -> String {
+    let last_user = request["messages"]
+        .as_array()
+        .and_then(|messages| messages.iter().rev().find(|m| m["role"] == "user"))
+        .map(|m| match &m["content"] {
+            Value::String(text) => text.clone(),
+            // Images are named rather than echoed as base64
+            Value::Array(blocks) => blocks
+                .iter()
+                .map(|block| match block["type"].as_str() {
+                    Some("text") => block["text"].as_str().unwrap_or_default().to_string(),
+                    Some("image") => format!(
+                        "[{}]",
+                        block["source"]["media_type"].as_str().unwrap_or("image")
+                    ),
+                    _ => block.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            content => content.to_string(),
+        })
+        .unwrap_or_default();
+    format!("You said: {}", last_user)
+}
+
+fn error_body(kind: &str, message: &str) -> Value {
+    json!({ "type": "error", "error": { "type": kind, "message": message } })
+}
+
+/// The error body the real API sends for `status`.
+fn default_error(status: u16) -> Value {
+    let (kind, message) = match status {
+        400 => ("invalid_request_error", "Invalid request"),
+        401 => ("authentication_error", "invalid x-api-key"),
+        403 => ("permission_error", "Permission denied"),
+        404 => ("not_found_error", "Not found"),
+        413 => (
+            "request_too_large",
+            "Request exceeds the maximum allowed size",
+        ),
+        429 => ("rate_limit_error", "Rate limited"),
+        529 => ("overloaded_error", "Overloaded"),
+        _ => ("api_error", "Internal server error"),
+    };
+    error_body(kind, message)
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        529 => "Overloaded",
+        _ => "Unknown",
+    }
+}
+
+async fn write_json(
+    stream: &mut TcpStream,
+    status: u16,
+    headers: &HashMap<String, String>,
+    body: &Value,
+) -> Result<()> {
+    let body = body.to_string();
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n",
+        status,
+        reason_phrase(status),
+        body.len()
+    );
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    response.push_str(&body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads one HTTP/1.1 request, returning its method, path and body.
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<(String, String, Vec<u8>)> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let byte = stream
+            .read_u8()
+            .await
+            .context("connection closed before request")?;
+        head.push(byte);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0);
+
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await?;
+    Ok((method, path, body))
+}
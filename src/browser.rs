@@ -0,0 +1,241 @@
+use std::cmp::Reverse;
+
+use crate::storage::ConversationSummary;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Updated,
+    Title,
+    Messages,
+}
+
+impl SortOrder {
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::Updated => SortOrder::Title,
+            SortOrder::Title => SortOrder::Messages,
+            SortOrder::Messages => SortOrder::Updated,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SortOrder::Updated => "updated",
+            SortOrder::Title => "title",
+            SortOrder::Messages => "messages",
+        }
+    }
+}
+
+/// What the keyboard is currently editing inside the browser.
+#[derive(Clone, PartialEq)]
+pub enum BrowseInput {
+    None,
+    Filter,
+    Rename(String),
+    ConfirmDelete,
+}
+
+/// State of the saved-conversation browser overlay.
+pub struct Browser {
+    pub entries: Vec<ConversationSummary>,
+    pub filter: String,
+    pub sort: SortOrder,
+    pub selected: usize,
+    pub input: BrowseInput,
+}
+
+impl Browser {
+    pub fn new(entries: Vec<ConversationSummary>) -> Self {
+        Self {
+            entries,
+            filter: String::new(),
+            sort: SortOrder::Updated,
+            selected: 0,
+            input: BrowseInput::None,
+        }
+    }
+
+    /// Entries matching the filter, in the current sort order.
+    pub fn visible(&self) -> Vec<&ConversationSummary> {
+        let mut visible: Vec<&ConversationSummary> = self
+            .entries
+            .iter()
+            .filter(|e| fuzzy_match(&self.filter, &e.title))
+            .collect();
+
+        match self.sort {
+            SortOrder::Updated => visible.sort_by_key(|e| Reverse(e.updated)),
+            SortOrder::Title => visible.sort_by_key(|e| e.title.to_lowercase()),
+            SortOrder::Messages => visible.sort_by_key(|e| Reverse(e.message_count)),
+        }
+
+        visible
+    }
+
+    pub fn selected_entry(&self) -> Option<&ConversationSummary> {
+        self.visible().get(self.selected).copied()
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.visible().len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        if self.selected > 0 {
+            self.selected -= 1;
+        }
+    }
+
+    pub fn cycle_sort(&mut self) {
+        self.sort = self.sort.next();
+        self.selected = 0;
+    }
+
+    pub fn push_filter(&mut self, c: char) {
+        self.filter.push(c);
+        self.selected = 0;
+    }
+
+    pub fn pop_filter(&mut self) {
+        self.filter.pop();
+        self.selected = 0;
+    }
+
+    /// Clamps the selection after entries were removed.
+    pub fn clamp_selection(&mut self) {
+        let len = self.visible().len();
+        if self.selected >= len {
+            self.selected = len.saturating_sub(1);
+        }
+    }
+}
+
+/// Case-insensitive subsequence match: every character of `pattern` must
+/// appear in `text` in order, not necessarily adjacent.
+fn fuzzy_match(pattern: &str, text: &str) -> bool {
+    let mut text_chars = text.chars().flat_map(char::to_lowercase);
+    pattern
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !c.is_whitespace())
+        .all(|p| text_chars.any(|t| t == p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeDelta};
+
+    fn entry(title: &str, message_count: usize, age_mins: i64) -> ConversationSummary {
+        ConversationSummary {
+            path: format!("{}.json", title).into(),
+            id: title.to_string(),
+            title: title.to_string(),
+            message_count,
+            updated: Local::now() - TimeDelta::minutes(age_mins),
+            preview: Vec::new(),
+        }
+    }
+
+    fn browser() -> Browser {
+        Browser::new(vec![
+            entry("Rust lifetimes", 4, 30),
+            entry("config file parsing", 10, 10),
+            entry("Grocery list", 2, 60),
+        ])
+    }
+
+    fn titles(browser: &Browser) -> Vec<&str> {
+        browser.visible().iter().map(|e| e.title.as_str()).collect()
+    }
+
+    #[test]
+    fn fuzzy_match_needs_the_characters_in_order() {
+        assert!(fuzzy_match("cfg", "config file"));
+        assert!(fuzzy_match("conf", "config file"));
+        assert!(!fuzzy_match("gfc", "config file"));
+        assert!(!fuzzy_match("configs", "config file"));
+    }
+
+    #[test]
+    fn fuzzy_match_ignores_case_and_spaces() {
+        assert!(fuzzy_match("RUST", "Rust lifetimes"));
+        assert!(fuzzy_match("rust life", "Rust lifetimes"));
+        assert!(fuzzy_match("", "anything"));
+        assert!(fuzzy_match("", ""));
+        assert!(!fuzzy_match("a", ""));
+    }
+
+    #[test]
+    fn filters_and_sorts_entries() {
+        let mut browser = browser();
+        assert_eq!(
+            titles(&browser),
+            ["config file parsing", "Rust lifetimes", "Grocery list"]
+        );
+
+        browser.cycle_sort();
+        assert!(browser.sort == SortOrder::Title);
+        assert_eq!(
+            titles(&browser),
+            ["config file parsing", "Grocery list", "Rust lifetimes"]
+        );
+
+        browser.cycle_sort();
+        assert!(browser.sort == SortOrder::Messages);
+        assert_eq!(
+            titles(&browser),
+            ["config file parsing", "Rust lifetimes", "Grocery list"]
+        );
+
+        for c in "ie".chars() {
+            browser.push_filter(c);
+        }
+        // "Grocery list" has no e after its i
+        assert_eq!(titles(&browser), ["config file parsing", "Rust lifetimes"]);
+    }
+
+    #[test]
+    fn nothing_is_selected_without_a_match() {
+        let mut browser = browser();
+        for c in "xyz".chars() {
+            browser.push_filter(c);
+        }
+        assert!(titles(&browser).is_empty());
+        assert!(browser.selected_entry().is_none());
+        browser.select_next();
+        assert_eq!(browser.selected, 0);
+
+        browser.pop_filter();
+        browser.pop_filter();
+        browser.pop_filter();
+        assert_eq!(titles(&browser).len(), 3);
+    }
+
+    #[test]
+    fn selection_stays_within_the_visible_entries() {
+        let mut browser = browser();
+        browser.select_next();
+        browser.select_next();
+        browser.select_next();
+        assert_eq!(browser.selected, 2);
+        assert_eq!(browser.selected_entry().unwrap().title, "Grocery list");
+
+        // Typing starts over at the best match
+        browser.push_filter('g');
+        assert_eq!(browser.selected, 0);
+
+        browser.filter.clear();
+        browser.selected = 2;
+        browser.entries.pop();
+        browser.clamp_selection();
+        assert_eq!(browser.selected, 1);
+
+        browser.select_prev();
+        browser.select_prev();
+        assert_eq!(browser.selected, 0);
+    }
+}
@@ -0,0 +1,378 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::tools::{truncate_output, Tool, ToolFuture, ToolRegistry};
+
+const MAX_GREP_MATCHES: usize = 200;
+/// Larger files are never read: read_file refuses them and grep skips them.
+const MAX_FILE_BYTES: u64 = 1_000_000;
+const MAX_LIST_ENTRIES: usize = 1000;
+
+/// Adds the built-in file and shell tools. They work on `root`, normally
+/// the directory claude-tui was started from; paths are resolved against it
+/// and may not leave it.
+pub fn register(registry: &mut ToolRegistry, root: PathBuf, shell_timeout: Duration) {
+    let root = Arc::new(root);
+    registry.register(Arc::new(ReadFile { root: root.clone() }));
+    registry.register(Arc::new(ListDirectory { root: root.clone() }));
+    registry.register(Arc::new(Grep { root: root.clone() }));
+    registry.register(Arc::new(RunShell {
+        root,
+        timeout: shell_timeout,
+    }));
+}
+
+fn parse_input<T: DeserializeOwned>(input: Value) -> Result<T> {
+    serde_json::from_value(input).context("invalid input")
+}
+
+/// Resolves `path` inside `root`, refusing anything that escapes it through
+/// `..` or symlinks.
+fn resolve(root: &Path, path: &str) -> Result<PathBuf> {
+    let root = root.canonicalize()?;
+    let resolved = root
+        .join(path)
+        .canonicalize()
+        .with_context(|| format!("{}: not found", path))?;
+    if !resolved.starts_with(&root) {
+        bail!("{}: outside the working directory", path);
+    }
+    Ok(resolved)
+}
+
+/// Path shown to the model, relative to the root.
+fn display_path(root: &Path, path: &Path) -> String {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    path.strip_prefix(&root)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// Reads a regular file of at most `MAX_FILE_BYTES`. The read itself is
+/// capped too, in case the file grows after its size was checked.
+fn read_limited(path: &Path) -> Result<Vec<u8>> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_file() {
+        bail!("not a regular file");
+    }
+    if metadata.len() > MAX_FILE_BYTES {
+        bail!("larger than {} bytes", MAX_FILE_BYTES);
+    }
+    let mut bytes = Vec::new();
+    fs::File::open(path)?
+        .take(MAX_FILE_BYTES + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_FILE_BYTES {
+        bail!("larger than {} bytes", MAX_FILE_BYTES);
+    }
+    Ok(bytes)
+}
+
+/// Runs blocking filesystem work off the async runtime.
+fn blocking<F>(work: F) -> ToolFuture
+where
+    F: FnOnce() -> Result<String> + Send + 'static,
+{
+    Box::pin(async move { tokio::task::spawn_blocking(work).await? })
+}
+
+struct ReadFile {
+    root: Arc<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct ReadFileInput {
+    path: String,
+    /// First line to return, starting at 1.
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+impl Tool for ReadFile {
+    fn name(&self) -> &str {
+        "read_file"
+    }
+
+    fn description(&self) -> &str {
+        "Read a text file of up to 1 MB in the working directory. Optionally return only `limit` lines starting at line `offset` (1-based)."
+    }
+
+    fn input_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "Path relative to the working directory" },
+                "offset": { "type": "integer", "minimum": 1 },
+                "limit": { "type": "integer", "minimum": 1 },
+            },
+            "required": ["path"],
+        })
+    }
+
+    fn run(&self, input: Value) -> ToolFuture {
+        let root = self.root.clone();
+        blocking(move || {
+            let input: ReadFileInput = parse_input(input)?;
+            let path = resolve(&root, &input.path)?;
+            let bytes =
+                read_limited(&path).with_context(|| format!("{}: could not read", input.path))?;
+            let content =
+                String::from_utf8(bytes).map_err(|_| anyhow!("{}: not a text file", input.path))?;
+
+            if input.offset.is_none() && input.limit.is_none() {
+                return Ok(truncate_output(content));
+            }
+            let skip = input.offset.unwrap_or(1).saturating_sub(1);
+            let lines: Vec<&str> = content
+                .lines()
+                .skip(skip)
+                .take(input.limit.unwrap_or(usize::MAX))
+                .collect();
+            Ok(truncate_output(lines.join("\n")))
+        })
+    }
+}
+
+struct ListDirectory {
+    root: Arc<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct ListDirectoryInput {
+    #[serde(default = "current_dir")]
+    path: String,
+}
+
+fn current_dir() -> String {
+    ".".to_string()
+}
+
+impl Tool for ListDirectory {
+    fn name(&self) -> &str {
+        "list_directory"
+    }
+
+    fn description(&self) -> &str {
+        "List the entries of a directory in the working directory. Directories end with a slash."
+    }
+
+    fn input_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string", "description": "Path relative to the working directory, default \".\"" },
+            },
+        })
+    }
+
+    fn run(&self, input: Value) -> ToolFuture {
+        let root = self.root.clone();
+        blocking(move || {
+            let input: ListDirectoryInput = parse_input(input)?;
+            let path = resolve(&root, &input.path)?;
+            let mut entries: Vec<String> = fs::read_dir(&path)
+                .with_context(|| format!("{}: could not list", input.path))?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    match entry.file_type() {
+                        Ok(kind) if kind.is_dir() => format!("{}/", name),
+                        _ => name,
+                    }
+                })
+                .collect();
+            entries.sort();
+
+            let total = entries.len();
+            entries.truncate(MAX_LIST_ENTRIES);
+            if total > MAX_LIST_ENTRIES {
+                entries.push(format!("[{} more entries]", total - MAX_LIST_ENTRIES));
+            }
+            if entries.is_empty() {
+                return Ok("(empty directory)".to_string());
+            }
+            Ok(entries.join("\n"))
+        })
+    }
+}
+
+struct Grep {
+    root: Arc<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct GrepInput {
+    pattern: String,
+    #[serde(default = "current_dir")]
+    path: String,
+    #[serde(default)]
+    ignore_case: bool,
+}
+
+impl Tool for Grep {
+    fn name(&self) -> &str {
+        "grep"
+    }
+
+    fn description(&self) -> &str {
+        "Search files under a path in the working directory for lines containing a literal string. Hidden files and directories, symlinks and files over 1 MB are skipped. Returns `path:line: text` for each match."
+    }
+
+    fn input_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "pattern": { "type": "string", "description": "Literal text to search for" },
+                "path": { "type": "string", "description": "File or directory to search, default \".\"" },
+                "ignore_case": { "type": "boolean" },
+            },
+            "required": ["pattern"],
+        })
+    }
+
+    fn run(&self, input: Value) -> ToolFuture {
+        let root = self.root.clone();
+        blocking(move || {
+            let input: GrepInput = parse_input(input)?;
+            if input.pattern.is_empty() {
+                bail!("pattern is empty");
+            }
+            let start = resolve(&root, &input.path)?;
+            let pattern = if input.ignore_case {
+                input.pattern.to_lowercase()
+            } else {
+                input.pattern.clone()
+            };
+
+            let mut matches = Vec::new();
+            let mut pending = vec![start];
+            while let Some(path) = pending.pop() {
+                // Symlinks are never followed: they could lead out of the
+                // root or around in a loop
+                let Ok(metadata) = fs::symlink_metadata(&path) else {
+                    continue;
+                };
+                if metadata.is_symlink() {
+                    continue;
+                }
+                if metadata.is_dir() {
+                    let Ok(entries) = fs::read_dir(&path) else {
+                        continue;
+                    };
+                    let mut children: Vec<PathBuf> = entries
+                        .filter_map(|entry| entry.ok())
+                        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                        .map(|entry| entry.path())
+                        .collect();
+                    // Reversed so files come off the stack in name order
+                    children.sort_by(|a, b| b.cmp(a));
+                    pending.extend(children);
+                    continue;
+                }
+                // Binary, unreadable, large and special files are skipped
+                let Some(content) = read_limited(&path)
+                    .ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                else {
+                    continue;
+                };
+                for (i, line) in content.lines().enumerate() {
+                    let found = if input.ignore_case {
+                        line.to_lowercase().contains(&pattern)
+                    } else {
+                        line.contains(&pattern)
+                    };
+                    if found {
+                        matches.push(format!(
+                            "{}:{}: {}",
+                            display_path(&root, &path),
+                            i + 1,
+                            line.trim()
+                        ));
+                        if matches.len() == MAX_GREP_MATCHES {
+                            matches.push("[more matches not shown]".to_string());
+                            return Ok(truncate_output(matches.join("\n")));
+                        }
+                    }
+                }
+            }
+
+            if matches.is_empty() {
+                return Ok("No matches".to_string());
+            }
+            Ok(truncate_output(matches.join("\n")))
+        })
+    }
+}
+
+struct RunShell {
+    root: Arc<PathBuf>,
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+struct RunShellInput {
+    command: String,
+}
+
+impl Tool for RunShell {
+    fn name(&self) -> &str {
+        "run_shell"
+    }
+
+    fn description(&self) -> &str {
+        "Run a shell command with `sh -c` in the working directory and return its exit status, stdout and stderr."
+    }
+
+    fn input_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string" },
+            },
+            "required": ["command"],
+        })
+    }
+
+    fn run(&self, input: Value) -> ToolFuture {
+        let root = self.root.clone();
+        let timeout = self.timeout;
+        Box::pin(async move {
+            let input: RunShellInput = parse_input(input)?;
+            let child = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(&input.command)
+                .current_dir(root.as_path())
+                .stdin(std::process::Stdio::null())
+                .kill_on_drop(true)
+                .output();
+            let output = tokio::time::timeout(timeout, child)
+                .await
+                .map_err(|_| anyhow!("timed out after {}s", timeout.as_secs()))?
+                .context("could not run sh")?;
+
+            let mut result = match output.status.code() {
+                Some(code) => format!("exit status {}\n", code),
+                None => "killed by a signal\n".to_string(),
+            };
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stdout.is_empty() {
+                result.push_str(&format!("stdout:\n{}\n", stdout.trim_end()));
+            }
+            if !stderr.is_empty() {
+                result.push_str(&format!("stderr:\n{}\n", stderr.trim_end()));
+            }
+            Ok(truncate_output(result))
+        })
+    }
+}
@@ -0,0 +1,778 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::style::Color;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::conversation::Usage;
+
+/// User configuration, read from `config.toml` in the XDG config directory.
+/// Every field is optional; missing entries fall back to the defaults.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub model: Option<String>,
+    pub max_tokens: u32,
+    pub temperature: Option<f32>,
+    /// Input tokens the model accepts; a warning is shown as a conversation
+    /// approaches it.
+    pub context_window: u64,
+    /// System prompt given to every new conversation.
+    pub system_prompt: Option<String>,
+    pub storage_dir: Option<PathBuf>,
+    pub api_base_url: Option<String>,
+    /// Rows the input box may grow to before it scrolls.
+    pub input_max_height: usize,
+    pub retry: RetryConfig,
+    pub tools: ToolsConfig,
+    /// MCP servers to launch, by name.
+    pub mcp_servers: BTreeMap<String, McpServerConfig>,
+    /// Prices by model name or name prefix, on top of the built-in table.
+    pub prices: HashMap<String, Price>,
+    pub theme: Theme,
+    pub keys: KeyBindings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            model: None,
+            max_tokens: 4096,
+            temperature: None,
+            context_window: 200_000,
+            system_prompt: None,
+            storage_dir: None,
+            api_base_url: None,
+            input_max_height: 8,
+            retry: RetryConfig::default(),
+            tools: ToolsConfig::default(),
+            mcp_servers: BTreeMap::new(),
+            prices: HashMap::new(),
+            theme: Theme::default(),
+            keys: KeyBindings::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("claude-tui")
+            .join("config.toml")
+    }
+
+    /// Loads the config file. A missing file yields the defaults; an invalid
+    /// one yields the defaults together with a description of the problem.
+    pub fn load() -> (Self, Option<String>) {
+        match Self::try_load() {
+            Ok(config) => (config, None),
+            Err(e) => (Self::default(), Some(e)),
+        }
+    }
+
+    pub fn try_load() -> Result<Self, String> {
+        let path = Self::path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Self::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(content).map_err(|e| {
+            // Report the line rather than the multi-line snippet toml renders
+            let line = e
+                .span()
+                .map(|span| content[..span.start].matches('\n').count() + 1);
+            match line {
+                Some(line) => format!("line {}: {}", line, e.message()),
+                None => e.message().to_string(),
+            }
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.max_tokens == 0 {
+            return Err("max_tokens must be greater than 0".to_string());
+        }
+        if let Some(t) = self.temperature {
+            if !(0.0..=1.0).contains(&t) {
+                return Err(format!("temperature must be between 0 and 1, got {}", t));
+            }
+        }
+        if self.context_window == 0 {
+            return Err("context_window must be greater than 0".to_string());
+        }
+        if self.input_max_height == 0 {
+            return Err("input_max_height must be at least 1".to_string());
+        }
+        if self.tools.shell_timeout_secs == 0 {
+            return Err("tools.shell_timeout_secs must be greater than 0".to_string());
+        }
+        if self.tools.max_rounds == 0 {
+            return Err("tools.max_rounds must be greater than 0".to_string());
+        }
+        for (name, server) in &self.mcp_servers {
+            if server.command.is_empty() {
+                return Err(format!("mcp_servers.{}.command must not be empty", name));
+            }
+        }
+        let bindings = self.keys.bindings();
+        for (i, (name, binding, _)) in bindings.iter().enumerate() {
+            for (other_name, other, _) in &bindings[i + 1..] {
+                if binding.conflicts(other) {
+                    return Err(format!(
+                        "keys.{} and keys.{} are both bound to `{}`",
+                        name, other_name, other
+                    ));
+                }
+            }
+        }
+        if !(0.2..=1.0).contains(&self.theme.bubble_width) {
+            return Err(format!(
+                "theme.bubble_width must be between 0.2 and 1, got {}",
+                self.theme.bubble_width
+            ));
+        }
+        Ok(())
+    }
+
+    /// The price of `model`, from the config file or the built-in table. Keys
+    /// match exactly or as a prefix, so `claude-sonnet-4` covers every
+    /// snapshot of that model; the longest match wins.
+    pub fn price(&self, model: &str) -> Option<Price> {
+        let configured = self
+            .prices
+            .iter()
+            .map(|(name, price)| (name.as_str(), *price));
+        let built_in = DEFAULT_PRICES.iter().map(|&(name, input, output)| {
+            (
+                name,
+                Price {
+                    input,
+                    output,
+                    cache_write: None,
+                    cache_read: None,
+                },
+            )
+        });
+        // max_by_key keeps the last of equal matches, so configured prices
+        // override built-in ones for the same name
+        built_in
+            .chain(configured)
+            .filter(|(name, _)| model.starts_with(name))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| price)
+    }
+
+    /// The storage directory with a leading `~` expanded.
+    pub fn storage_dir(&self) -> Option<PathBuf> {
+        let dir = self.storage_dir.as_ref()?;
+        match (dir.strip_prefix("~"), dirs::home_dir()) {
+            (Ok(rest), Some(home)) => Some(home.join(rest)),
+            _ => Some(dir.clone()),
+        }
+    }
+}
+
+/// Built-in prices in USD per million input and output tokens.
+const DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+    ("claude-opus-4", 15.0, 75.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-3-haiku", 0.25, 1.25),
+];
+
+/// USD per million tokens.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+    /// Defaults to 1.25x the input price.
+    pub cache_write: Option<f64>,
+    /// Defaults to 0.1x the input price.
+    pub cache_read: Option<f64>,
+}
+
+impl Price {
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let cache_write = self.cache_write.unwrap_or(self.input * 1.25);
+        let cache_read = self.cache_read.unwrap_or(self.input * 0.1);
+        (usage.input_tokens as f64 * self.input
+            + usage.output_tokens as f64 * self.output
+            + usage.cache_creation_input_tokens as f64 * cache_write
+            + usage.cache_read_input_tokens as f64 * cache_read)
+            / 1_000_000.0
+    }
+}
+
+/// How failed API requests are retried.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for each one after.
+    pub base_delay_ms: u64,
+    /// Upper bound on any single wait, including server-requested ones.
+    pub max_delay_secs: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 4,
+            base_delay_ms: 1000,
+            max_delay_secs: 60,
+        }
+    }
+}
+
+/// Built-in tools and when they may run without asking.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolsConfig {
+    /// Offer the built-in file and shell tools to the model.
+    pub enabled: bool,
+    /// Tools that run without an approval prompt.
+    pub allow: Vec<String>,
+    /// Report tool calls back to the model without running them.
+    pub dry_run: bool,
+    pub shell_timeout_secs: u64,
+    /// Times tool results are sent back to the model for one message before
+    /// it stops and waits for the user.
+    pub max_rounds: u32,
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            allow: Vec::new(),
+            dry_run: false,
+            shell_timeout_secs: 60,
+            max_rounds: 25,
+        }
+    }
+}
+
+/// A Model Context Protocol server, run as a child process and spoken to
+/// over stdio.
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct McpServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Added to the environment claude-tui runs with.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// Borders, titles and selections.
+    #[serde(deserialize_with = "color")]
+    pub accent: Color,
+    #[serde(deserialize_with = "color")]
+    pub user: Color,
+    #[serde(deserialize_with = "color")]
+    pub assistant: Color,
+    /// Hints, timestamps and other secondary text.
+    #[serde(deserialize_with = "color")]
+    pub muted: Color,
+    /// Border of the input box outside insert mode.
+    #[serde(deserialize_with = "color")]
+    pub inactive: Color,
+    #[serde(deserialize_with = "color")]
+    pub status: Color,
+    #[serde(deserialize_with = "color")]
+    pub error: Color,
+    /// Prompts inside overlays.
+    #[serde(deserialize_with = "color")]
+    pub prompt: Color,
+    /// Maximum bubble width as a fraction of the message area.
+    pub bubble_width: f32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            accent: Color::Cyan,
+            user: Color::Blue,
+            assistant: Color::Green,
+            muted: Color::DarkGray,
+            inactive: Color::Gray,
+            status: Color::Green,
+            error: Color::Red,
+            prompt: Color::Yellow,
+            bubble_width: 0.7,
+        }
+    }
+}
+
+/// Parses a color given by name (`"cyan"`, `"darkgray"`), hex (`"#88c0d0"`)
+/// or ANSI index (`"208"`).
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Color::from_str(&value).map_err(|_| de::Error::custom(format!("invalid color `{}`", value)))
+}
+
+/// Actions that can be bound to keys in normal mode.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Quit,
+    Insert,
+    Help,
+    NewConversation,
+    CloseConversation,
+    PrevTab,
+    NextTab,
+    Save,
+    Browse,
+    Cancel,
+    ToggleRawMarkdown,
+    ToggleThinking,
+    EditInput,
+    EditSystemPrompt,
+    ScrollDown,
+    ScrollUp,
+    HalfPageDown,
+    HalfPageUp,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub quit: KeyBinding,
+    pub insert: KeyBinding,
+    pub help: KeyBinding,
+    pub new_conversation: KeyBinding,
+    pub close_conversation: KeyBinding,
+    pub prev_tab: KeyBinding,
+    pub next_tab: KeyBinding,
+    pub save: KeyBinding,
+    pub browse: KeyBinding,
+    pub cancel: KeyBinding,
+    pub toggle_raw_markdown: KeyBinding,
+    pub toggle_thinking: KeyBinding,
+    pub edit_input: KeyBinding,
+    pub edit_system_prompt: KeyBinding,
+    pub scroll_down: KeyBinding,
+    pub scroll_up: KeyBinding,
+    pub half_page_down: KeyBinding,
+    pub half_page_up: KeyBinding,
+    pub page_down: KeyBinding,
+    pub page_up: KeyBinding,
+    pub top: KeyBinding,
+    pub bottom: KeyBinding,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let key = |s: &str| KeyBinding::from_str(s).expect("valid default binding");
+        Self {
+            quit: key("q"),
+            insert: key("i"),
+            help: key("?"),
+            new_conversation: key("ctrl+n"),
+            close_conversation: key("ctrl+w"),
+            prev_tab: key("ctrl+h"),
+            next_tab: key("ctrl+l"),
+            save: key("ctrl+s"),
+            browse: key("ctrl+o"),
+            cancel: key("ctrl+c"),
+            toggle_raw_markdown: key("m"),
+            toggle_thinking: key("t"),
+            edit_input: key("ctrl+g"),
+            edit_system_prompt: key("S"),
+            scroll_down: key("j"),
+            scroll_up: key("k"),
+            half_page_down: key("ctrl+d"),
+            half_page_up: key("ctrl+u"),
+            page_down: key("ctrl+f"),
+            page_up: key("ctrl+b"),
+            top: key("g"),
+            bottom: key("G"),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Each binding with its name in the config file and its action.
+    fn bindings(&self) -> [(&'static str, &KeyBinding, Action); 22] {
+        [
+            ("quit", &self.quit, Action::Quit),
+            ("insert", &self.insert, Action::Insert),
+            ("help", &self.help, Action::Help),
+            (
+                "new_conversation",
+                &self.new_conversation,
+                Action::NewConversation,
+            ),
+            (
+                "close_conversation",
+                &self.close_conversation,
+                Action::CloseConversation,
+            ),
+            ("prev_tab", &self.prev_tab, Action::PrevTab),
+            ("next_tab", &self.next_tab, Action::NextTab),
+            ("save", &self.save, Action::Save),
+            ("browse", &self.browse, Action::Browse),
+            ("cancel", &self.cancel, Action::Cancel),
+            (
+                "toggle_raw_markdown",
+                &self.toggle_raw_markdown,
+                Action::ToggleRawMarkdown,
+            ),
+            (
+                "toggle_thinking",
+                &self.toggle_thinking,
+                Action::ToggleThinking,
+            ),
+            ("edit_input", &self.edit_input, Action::EditInput),
+            (
+                "edit_system_prompt",
+                &self.edit_system_prompt,
+                Action::EditSystemPrompt,
+            ),
+            ("scroll_down", &self.scroll_down, Action::ScrollDown),
+            ("scroll_up", &self.scroll_up, Action::ScrollUp),
+            ("half_page_down", &self.half_page_down, Action::HalfPageDown),
+            ("half_page_up", &self.half_page_up, Action::HalfPageUp),
+            ("page_down", &self.page_down, Action::PageDown),
+            ("page_up", &self.page_up, Action::PageUp),
+            ("top", &self.top, Action::Top),
+            ("bottom", &self.bottom, Action::Bottom),
+        ]
+    }
+
+    pub fn action(&self, key: &KeyEvent) -> Option<Action> {
+        self.bindings()
+            .into_iter()
+            .find(|(_, binding, _)| binding.matches(key))
+            .map(|(_, _, action)| action)
+    }
+}
+
+/// A key with optional modifiers, written like `"q"`, `"ctrl+n"`,
+/// `"alt+enter"` or `"pagedown"`.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+    text: String,
+}
+
+impl KeyBinding {
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        // Shift is implied by the character itself ("G", "?"), so only
+        // Ctrl and Alt have to match exactly
+        let relevant = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        let code_matches = match (self.code, key.code) {
+            (KeyCode::Char(a), KeyCode::Char(b))
+                if self.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                a.eq_ignore_ascii_case(&b)
+            }
+            (a, b) => a == b,
+        };
+        code_matches && (key.modifiers & relevant) == (self.modifiers & relevant)
+    }
+
+    /// Whether one key press would trigger both bindings.
+    fn conflicts(&self, other: &KeyBinding) -> bool {
+        self.matches(&KeyEvent::new(other.code, other.modifiers))
+            || other.matches(&KeyEvent::new(self.code, self.modifiers))
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid key binding `{}`", s);
+        let mut modifiers = KeyModifiers::NONE;
+        let mut parts: Vec<&str> = s.split('+').collect();
+        // "+" and "ctrl++" bind the plus key
+        if s == "+" || s.ends_with("++") {
+            parts.pop();
+            parts.pop();
+            parts.push("+");
+        }
+        let key = parts.pop().filter(|k| !k.is_empty()).ok_or_else(invalid)?;
+
+        for modifier in parts {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(invalid()),
+            };
+        }
+
+        let code = match key.to_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            f if f.len() > 1 && f.starts_with('f') => {
+                KeyCode::F(f[1..].parse().map_err(|_| invalid())?)
+            }
+            _ => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return Err(invalid()),
+                }
+            }
+        };
+
+        Ok(Self {
+            code,
+            modifiers,
+            text: s.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for KeyBinding {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn parses_an_empty_file_as_the_defaults() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.max_tokens, 4096);
+        assert_eq!(config.tools.max_rounds, 25);
+        assert_eq!(config.theme.accent, Color::Cyan);
+        assert_eq!(config.keys.quit.to_string(), "q");
+    }
+
+    #[test]
+    fn parses_every_section() {
+        let config = Config::parse(
+            r##"
+            model = "claude-sonnet-4-5"
+            max_tokens = 8192
+            temperature = 0.5
+
+            [retry]
+            max_retries = 2
+
+            [tools]
+            max_rounds = 10
+
+            [mcp_servers.files]
+            command = "mcp-files"
+            args = ["--root", "."]
+
+            [prices.claude-custom]
+            input = 1.0
+            output = 2.0
+
+            [theme]
+            accent = "#88c0d0"
+            muted = "darkgray"
+            error = "208"
+
+            [keys]
+            quit = "ctrl+q"
+            top = "home"
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.model.as_deref(), Some("claude-sonnet-4-5"));
+        assert_eq!(config.max_tokens, 8192);
+        assert_eq!(config.temperature, Some(0.5));
+        assert_eq!(config.retry.max_retries, 2);
+        assert_eq!(config.retry.base_delay_ms, 1000);
+        assert_eq!(config.tools.max_rounds, 10);
+        assert_eq!(config.mcp_servers["files"].args, ["--root", "."]);
+        assert!(config.price("claude-custom-2").is_some());
+        assert_eq!(config.theme.accent, Color::Rgb(0x88, 0xc0, 0xd0));
+        assert_eq!(config.theme.muted, Color::DarkGray);
+        assert_eq!(config.theme.error, Color::Indexed(208));
+        assert!(
+            config
+                .keys
+                .action(&key(KeyCode::Char('q'), KeyModifiers::CONTROL))
+                == Some(Action::Quit)
+        );
+        assert!(config.keys.action(&key(KeyCode::Home, KeyModifiers::NONE)) == Some(Action::Top));
+    }
+
+    #[test]
+    fn reports_the_line_of_a_parse_error() {
+        let error = Config::parse("model = \"a\"\nmax_tokenz = 10\n")
+            .err()
+            .unwrap();
+        assert!(error.starts_with("line 2:"), "{}", error);
+        assert!(error.contains("max_tokenz"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        for (content, expected) in [
+            ("max_tokens = 0", "max_tokens"),
+            ("temperature = 1.5", "temperature"),
+            ("context_window = 0", "context_window"),
+            ("input_max_height = 0", "input_max_height"),
+            (
+                "[tools]\nshell_timeout_secs = 0",
+                "tools.shell_timeout_secs",
+            ),
+            ("[tools]\nmax_rounds = 0", "tools.max_rounds"),
+            (
+                "[mcp_servers.empty]\ncommand = \"\"",
+                "mcp_servers.empty.command",
+            ),
+            ("[theme]\nbubble_width = 0.1", "theme.bubble_width"),
+        ] {
+            let error = Config::parse(content).err().unwrap();
+            assert!(error.starts_with(expected), "{}: {}", content, error);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_theme_colors() {
+        let error = Config::parse("[theme]\naccent = \"blurple\"")
+            .err()
+            .unwrap();
+        assert!(error.contains("invalid color `blurple`"), "{}", error);
+        assert!(Config::parse("[theme]\naccent = \"#12345\"").is_err());
+    }
+
+    #[test]
+    fn parses_key_bindings() {
+        for (text, code, modifiers) in [
+            ("q", KeyCode::Char('q'), KeyModifiers::NONE),
+            ("G", KeyCode::Char('G'), KeyModifiers::NONE),
+            ("ctrl+n", KeyCode::Char('n'), KeyModifiers::CONTROL),
+            ("Control+N", KeyCode::Char('N'), KeyModifiers::CONTROL),
+            ("alt+enter", KeyCode::Enter, KeyModifiers::ALT),
+            ("meta+esc", KeyCode::Esc, KeyModifiers::ALT),
+            ("shift+tab", KeyCode::Tab, KeyModifiers::SHIFT),
+            (
+                "ctrl+alt+del",
+                KeyCode::Delete,
+                KeyModifiers::CONTROL | KeyModifiers::ALT,
+            ),
+            ("pagedown", KeyCode::PageDown, KeyModifiers::NONE),
+            ("space", KeyCode::Char(' '), KeyModifiers::NONE),
+            ("f5", KeyCode::F(5), KeyModifiers::NONE),
+            ("f", KeyCode::Char('f'), KeyModifiers::NONE),
+            ("ctrl++", KeyCode::Char('+'), KeyModifiers::CONTROL),
+            ("+", KeyCode::Char('+'), KeyModifiers::NONE),
+        ] {
+            let binding: KeyBinding = text.parse().unwrap();
+            assert_eq!(binding.code, code, "{}", text);
+            assert_eq!(binding.modifiers, modifiers, "{}", text);
+            assert_eq!(binding.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_key_bindings() {
+        for text in ["", "ctrl+", "hyper+x", "ctrl+foo", "fx", "f99999", "ab"] {
+            let error = text.parse::<KeyBinding>().err().unwrap();
+            assert_eq!(error, format!("invalid key binding `{}`", text));
+        }
+        let error = Config::parse("[keys]\nquit = \"ctrl+\"").err().unwrap();
+        assert!(error.contains("invalid key binding `ctrl+`"), "{}", error);
+    }
+
+    #[test]
+    fn matches_keys_as_terminals_report_them() {
+        let ctrl_n: KeyBinding = "ctrl+n".parse().unwrap();
+        assert!(ctrl_n.matches(&key(KeyCode::Char('n'), KeyModifiers::CONTROL)));
+        assert!(ctrl_n.matches(&key(
+            KeyCode::Char('N'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT
+        )));
+        assert!(!ctrl_n.matches(&key(KeyCode::Char('n'), KeyModifiers::NONE)));
+        assert!(!ctrl_n.matches(&key(
+            KeyCode::Char('n'),
+            KeyModifiers::CONTROL | KeyModifiers::ALT
+        )));
+
+        let big_g: KeyBinding = "G".parse().unwrap();
+        assert!(big_g.matches(&key(KeyCode::Char('G'), KeyModifiers::SHIFT)));
+        assert!(!big_g.matches(&key(KeyCode::Char('g'), KeyModifiers::NONE)));
+    }
+
+    #[test]
+    fn rejects_duplicate_bindings() {
+        let error = Config::parse("[keys]\ntop = \"q\"").err().unwrap();
+        assert_eq!(error, "keys.quit and keys.top are both bound to `q`");
+    }
+
+    #[test]
+    fn rejects_bindings_that_differ_only_in_case_under_ctrl() {
+        let error = Config::parse("[keys]\nsave = \"ctrl+N\"").err().unwrap();
+        assert_eq!(
+            error,
+            "keys.new_conversation and keys.save are both bound to `ctrl+N`"
+        );
+        // Without ctrl the case is part of the key
+        assert!(Config::parse("[keys]\ntop = \"Q\"").is_ok());
+    }
+
+    #[test]
+    fn swapping_bindings_is_not_a_conflict() {
+        let config = Config::parse("[keys]\nscroll_down = \"k\"\nscroll_up = \"j\"").unwrap();
+        assert!(
+            config
+                .keys
+                .action(&key(KeyCode::Char('k'), KeyModifiers::NONE))
+                == Some(Action::ScrollDown)
+        );
+    }
+}
@@ -1,17 +1,105 @@
+use std::collections::HashSet;
+
 use chrono::{DateTime, Local};
+use serde_json::Value;
 use uuid::Uuid;
 
-#[derive(Clone, Copy, PartialEq)]
+use crate::attachment::Attachment;
+
+#[derive(Clone, Copy, PartialEq, Hash)]
 pub enum Role {
     User,
     Assistant,
 }
 
+/// Token counts reported by the API for one reply.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_creation_input_tokens: u64,
+    pub cache_read_input_tokens: u64,
+}
+
+impl Usage {
+    /// Input tokens including those written to or read from the cache.
+    pub fn total_input(&self) -> u64 {
+        self.input_tokens + self.cache_creation_input_tokens + self.cache_read_input_tokens
+    }
+}
+
+impl std::ops::Sub for Usage {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            input_tokens: self.input_tokens.saturating_sub(other.input_tokens),
+            output_tokens: self.output_tokens.saturating_sub(other.output_tokens),
+            cache_creation_input_tokens: self
+                .cache_creation_input_tokens
+                .saturating_sub(other.cache_creation_input_tokens),
+            cache_read_input_tokens: self
+                .cache_read_input_tokens
+                .saturating_sub(other.cache_read_input_tokens),
+        }
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+    }
+}
+
+/// Extended thinking that preceded a reply. Blocks are sent back with later
+/// requests exactly as received, which the signature lets the API verify.
+#[derive(Clone, Hash)]
+pub enum ThinkingBlock {
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    /// Thinking flagged by safety systems, returned encrypted.
+    Redacted {
+        data: String,
+    },
+}
+
+/// A tool the model asked to run, with its result once it has run.
+#[derive(Clone)]
+pub struct ToolCall {
+    /// Id the API gave the call, echoed back with the result.
+    pub id: String,
+    pub name: String,
+    pub input: Value,
+    pub result: Option<ToolResult>,
+}
+
+#[derive(Clone, Hash)]
+pub struct ToolResult {
+    pub content: String,
+    pub is_error: bool,
+}
+
 #[derive(Clone)]
 pub struct Message {
     pub role: Role,
     pub content: String,
     pub timestamp: DateTime<Local>,
+    pub interrupted: bool,
+    /// Model that wrote an assistant reply.
+    pub model: Option<String>,
+    pub usage: Option<Usage>,
+    /// Why the model stopped, e.g. `end_turn` or `max_tokens`.
+    pub stop_reason: Option<String>,
+    pub thinking: Vec<ThinkingBlock>,
+    /// Tools requested by an assistant reply.
+    pub tool_calls: Vec<ToolCall>,
+    /// Images sent with a user message.
+    pub attachments: Vec<Attachment>,
 }
 
 impl Message {
@@ -20,8 +108,25 @@ impl Message {
             role,
             content,
             timestamp: Local::now(),
+            interrupted: false,
+            model: None,
+            usage: None,
+            stop_reason: None,
+            thinking: Vec::new(),
+            tool_calls: Vec::new(),
+            attachments: Vec::new(),
         }
     }
+
+    /// Tool calls that haven't run yet.
+    pub fn pending_tool_calls(&self) -> impl Iterator<Item = &ToolCall> {
+        self.tool_calls.iter().filter(|call| call.result.is_none())
+    }
+
+    /// Whether the reply was cut off by the `max_tokens` limit.
+    pub fn is_truncated(&self) -> bool {
+        self.stop_reason.as_deref() == Some("max_tokens")
+    }
 }
 
 pub struct Conversation {
@@ -29,7 +134,19 @@ pub struct Conversation {
     pub title: Option<String>,
     pub messages: Vec<Message>,
     pub system_prompt: Option<String>,
+    /// First visible rendered line of the message view.
     pub scroll_offset: usize,
+    /// Keep the view pinned to the bottom as new content arrives.
+    pub follow_bottom: bool,
+    /// Rendered height of all messages and of the visible area, in lines.
+    /// Updated on every draw.
+    pub content_height: usize,
+    pub viewport_height: usize,
+    pub is_loading: bool,
+    /// Tools the user allowed to run without asking again.
+    pub allowed_tools: HashSet<String>,
+    /// Whether the conversation has a file on disk.
+    pub saved: bool,
 }
 
 impl Conversation {
@@ -40,6 +157,12 @@ impl Conversation {
             messages: Vec::new(),
             system_prompt: None,
             scroll_offset: 0,
+            follow_bottom: true,
+            content_height: 0,
+            viewport_height: 0,
+            is_loading: false,
+            allowed_tools: HashSet::new(),
+            saved: false,
         }
     }
 
@@ -48,11 +171,36 @@ impl Conversation {
         self.generate_title();
     }
 
+    /// Cleans up after a reply that stopped early: an empty reply is removed
+    /// so the next request doesn't carry a blank assistant turn, and a
+    /// partial one is flagged as interrupted. A reply that called tools is
+    /// kept so their results aren't lost.
+    pub fn drop_or_interrupt_reply(&mut self) {
+        if let Some(last) = self.messages.last_mut() {
+            if last.role == Role::Assistant {
+                if last.content.is_empty() && last.tool_calls.is_empty() {
+                    self.messages.pop();
+                } else {
+                    last.interrupted = true;
+                }
+            }
+        }
+    }
+
+    /// Tokens used by every reply in the conversation.
+    pub fn total_usage(&self) -> Usage {
+        let mut total = Usage::default();
+        for usage in self.messages.iter().filter_map(|m| m.usage) {
+            total += usage;
+        }
+        total
+    }
+
     pub fn generate_title(&mut self) {
         if self.title.is_none() {
             if let Some(msg) = self.messages.iter().find(|m| matches!(m.role, Role::User)) {
                 let title: String = msg.content.chars().take(30).collect();
-                self.title = Some(if msg.content.len() > 30 {
+                self.title = Some(if msg.content.chars().count() > 30 {
                     format!("{}...", title)
                 } else {
                     title
@@ -65,25 +213,47 @@ impl Conversation {
         self.title.as_deref().unwrap_or("New Chat")
     }
 
-    pub fn scroll_up(&mut self) {
-        if self.scroll_offset > 0 {
-            self.scroll_offset -= 1;
-        }
+    pub fn max_scroll(&self) -> usize {
+        self.content_height.saturating_sub(self.viewport_height)
     }
 
-    pub fn scroll_down(&mut self, max_visible: usize) {
-        let max_scroll = self.messages.len().saturating_sub(max_visible);
-        if self.scroll_offset < max_scroll {
-            self.scroll_offset += 1;
-        }
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll_offset = self
+            .scroll_offset
+            .min(self.max_scroll())
+            .saturating_sub(lines);
+        self.follow_bottom = false;
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll_offset = (self.scroll_offset + lines).min(self.max_scroll());
+        self.follow_bottom = self.scroll_offset == self.max_scroll();
+    }
+
+    pub fn half_page_up(&mut self) {
+        self.scroll_up((self.viewport_height / 2).max(1));
+    }
+
+    pub fn half_page_down(&mut self) {
+        self.scroll_down((self.viewport_height / 2).max(1));
+    }
+
+    pub fn page_up(&mut self) {
+        self.scroll_up(self.viewport_height.max(1));
+    }
+
+    pub fn page_down(&mut self) {
+        self.scroll_down(self.viewport_height.max(1));
     }
 
     pub fn scroll_to_top(&mut self) {
         self.scroll_offset = 0;
+        self.follow_bottom = false;
     }
 
-    pub fn scroll_to_bottom(&mut self, max_visible: usize) {
-        self.scroll_offset = self.messages.len().saturating_sub(max_visible);
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_offset = self.max_scroll();
+        self.follow_bottom = true;
     }
 }
 
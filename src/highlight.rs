@@ -0,0 +1,101 @@
+use std::sync::OnceLock;
+
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::Span,
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{FontStyle, Theme, ThemeSet},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+const THEME: &str = "base16-ocean.dark";
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME_SET: OnceLock<ThemeSet> = OnceLock::new();
+    &THEME_SET.get_or_init(ThemeSet::load_defaults).themes[THEME]
+}
+
+/// Highlights `code` for the language named by a fence tag such as `rust`,
+/// `py` or `sh`. Returns one list of spans per source line, or `None` when
+/// the language is unknown.
+pub fn highlight(code: &str, lang: &str) -> Option<Vec<Vec<Span<'static>>>> {
+    let syntaxes = syntax_set();
+    let syntax = syntaxes
+        .find_syntax_by_token(lang)
+        .or_else(|| syntaxes.find_syntax_by_extension(lang))?;
+    let mut highlighter = HighlightLines::new(syntax, theme());
+
+    let mut lines = Vec::new();
+    for line in LinesWithEndings::from(code) {
+        let ranges = highlighter.highlight_line(line, syntaxes).ok()?;
+        let spans = ranges
+            .into_iter()
+            .map(|(style, text)| {
+                let text = text.trim_end_matches(['\n', '\r']).to_string();
+                Span::styled(text, convert_style(style))
+            })
+            .filter(|span| !span.content.is_empty())
+            .collect();
+        lines.push(spans);
+    }
+    Some(lines)
+}
+
+fn convert_style(style: syntect::highlighting::Style) -> Style {
+    let fg = style.foreground;
+    let mut converted = Style::default().fg(Color::Rgb(fg.r, fg.g, fg.b));
+    if style.font_style.contains(FontStyle::BOLD) {
+        converted = converted.add_modifier(Modifier::BOLD);
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        converted = converted.add_modifier(Modifier::ITALIC);
+    }
+    if style.font_style.contains(FontStyle::UNDERLINE) {
+        converted = converted.add_modifier(Modifier::UNDERLINED);
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(line: &[Span]) -> String {
+        line.iter().map(|s| s.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn finds_languages_by_name_and_extension() {
+        for lang in ["rust", "rs", "python", "py", "sh"] {
+            assert!(highlight("x = 1", lang).is_some(), "{}", lang);
+        }
+        assert!(highlight("x = 1", "no-such-language").is_none());
+    }
+
+    #[test]
+    fn keeps_the_text_of_every_line() {
+        let code = "fn main() {\n    println!(\"hi\");\n}";
+        let lines = highlight(code, "rust").unwrap();
+        let texts: Vec<String> = lines.iter().map(|line| text(line)).collect();
+        assert_eq!(texts, code.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn colors_tokens_with_the_theme() {
+        let lines = highlight("fn main() {}", "rust").unwrap();
+        let colors: Vec<_> = lines[0].iter().map(|s| s.style.fg).collect();
+        assert!(colors.iter().all(|c| matches!(c, Some(Color::Rgb(..)))));
+        // The keyword stands out from the name after it
+        let keyword = lines[0].iter().find(|s| s.content == "fn").unwrap();
+        let name = lines[0].iter().find(|s| s.content == "main").unwrap();
+        assert_ne!(keyword.style.fg, name.style.fg);
+    }
+}
@@ -0,0 +1,7 @@
+//! The API client and the conversation types it sends. They live in a
+//! library so the integration tests can drive them against `mock_server`.
+
+pub mod api;
+pub mod attachment;
+pub mod config;
+pub mod conversation;
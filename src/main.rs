@@ -1,33 +1,91 @@
-mod api;
 mod app;
-mod conversation;
+mod browser;
+mod builtin_tools;
+mod highlight;
+mod markdown;
+mod mcp;
 mod storage;
+mod text;
+mod tools;
 mod ui;
 
+use claude_tui::{api, attachment, config, conversation};
+
 use std::io::{self, stdout};
 use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    event::{
+        self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+        PushKeyboardEnhancementFlags,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{
+        disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
 };
 use ratatui::{backend::CrosstermBackend, Terminal};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
 
-use api::{ApiClient, StreamChunk};
-use app::{App, Mode};
-use conversation::Message;
+use api::{ApiClient, ApiError, Retry, StreamChunk};
+use app::{App, ApprovalChoice, CountRequest, EditTarget, Mode, RequestId, RequestKind, ToolStep};
+use browser::BrowseInput;
+use config::{Action, McpServerConfig};
+use conversation::{Message, ThinkingBlock, ToolCall, ToolResult, Usage};
+use mcp::McpSession;
 
 enum AppEvent {
     Key(crossterm::event::KeyEvent),
-    StreamChunk(String),
-    StreamDone,
-    StreamError(String),
+    Paste(String),
+    StreamChunk(RequestId, String),
+    StreamRetry(RequestId, Retry),
+    StreamUsage(RequestId, Usage),
+    StreamStop(RequestId, String),
+    StreamThinkingStart(RequestId, ThinkingBlock),
+    StreamThinking(RequestId, String),
+    StreamSignature(RequestId, String),
+    StreamToolUse(RequestId, ToolCall),
+    /// A tool finished: request, tool use id and result.
+    ToolResult(RequestId, String, ToolResult),
+    TokenCount(u64, u64),
+    /// An MCP server finished starting: name, generation and the session or
+    /// why it failed.
+    McpStarted(String, u64, Result<Arc<McpSession>, String>),
+    McpExited(String, u64),
+    StreamDone(RequestId),
+    StreamError(RequestId, ApiError),
+}
+
+impl AppEvent {
+    /// The request a stream or tool event belongs to.
+    fn request(&self) -> Option<RequestId> {
+        match self {
+            AppEvent::StreamChunk(id, _)
+            | AppEvent::StreamRetry(id, _)
+            | AppEvent::StreamUsage(id, _)
+            | AppEvent::StreamStop(id, _)
+            | AppEvent::StreamThinkingStart(id, _)
+            | AppEvent::StreamThinking(id, _)
+            | AppEvent::StreamSignature(id, _)
+            | AppEvent::StreamToolUse(id, _)
+            | AppEvent::ToolResult(id, _, _)
+            | AppEvent::StreamDone(id)
+            | AppEvent::StreamError(id, _) => Some(*id),
+            _ => None,
+        }
+    }
 }
 
+/// Whether keyboard enhancement flags were pushed and must be popped again.
+static KEYBOARD_ENHANCED: AtomicBool = AtomicBool::new(false);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Set up panic hook to restore terminal on panic
@@ -38,10 +96,8 @@ async fn main() -> Result<()> {
     }));
 
     // Initialize terminal
-    enable_raw_mode()?;
-    let mut stdout = stdout();
-    execute!(stdout, EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
+    setup_terminal()?;
+    let backend = CrosstermBackend::new(stdout());
     let mut terminal = Terminal::new(backend)?;
 
     // Run the app
@@ -54,27 +110,52 @@ async fn main() -> Result<()> {
     result
 }
 
+fn setup_terminal() -> Result<()> {
+    enable_raw_mode()?;
+    let mut stdout = stdout();
+    execute!(stdout, EnterAlternateScreen, EnableBracketedPaste)?;
+    // Lets terminals that support it report Shift+Enter distinctly from Enter
+    if supports_keyboard_enhancement().unwrap_or(false) {
+        execute!(
+            stdout,
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        )?;
+        KEYBOARD_ENHANCED.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
 fn restore_terminal() -> Result<()> {
+    if KEYBOARD_ENHANCED.swap(false, Ordering::Relaxed) {
+        execute!(io::stdout(), PopKeyboardEnhancementFlags)?;
+    }
     disable_raw_mode()?;
-    execute!(io::stdout(), LeaveAlternateScreen)?;
+    execute!(io::stdout(), DisableBracketedPaste, LeaveAlternateScreen)?;
     Ok(())
 }
 
 async fn run(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut App) -> Result<()> {
     let (tx, mut rx) = mpsc::channel::<AppEvent>(32);
 
-    // Clone API client for async tasks
-    let api_client: Option<Arc<ApiClient>> = app.api_client.take().map(Arc::new);
-
-    // Spawn keyboard event reader
+    // Spawn keyboard event reader. It stops reading while an external editor
+    // owns the terminal.
+    let input_paused = Arc::new(AtomicBool::new(false));
+    let paused = Arc::clone(&input_paused);
     let tx_keys = tx.clone();
     tokio::spawn(async move {
         loop {
+            if paused.load(Ordering::Relaxed) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                continue;
+            }
             if event::poll(std::time::Duration::from_millis(50)).unwrap_or(false) {
-                if let Ok(Event::Key(key)) = event::read() {
-                    if tx_keys.send(AppEvent::Key(key)).await.is_err() {
-                        break;
-                    }
+                let event = match event::read() {
+                    Ok(Event::Key(key)) => AppEvent::Key(key),
+                    Ok(Event::Paste(text)) => AppEvent::Paste(text),
+                    _ => continue,
+                };
+                if tx_keys.send(event).await.is_err() {
+                    break;
                 }
             }
         }
@@ -86,83 +167,127 @@ async fn run(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut Ap
         })?;
 
         if app.should_quit {
+            app.save_all();
             return Ok(());
         }
 
+        if let Some(target) = app.pending_edit.take() {
+            let initial = app.edit_text(target);
+            match edit_in_editor(terminal, &input_paused, &initial).await {
+                Ok(text) => app.apply_edit(target, text),
+                Err(e) => app.set_error(format!("Editor failed: {}", e)),
+            }
+            continue;
+        }
+
+        for (name, generation, config) in app.take_mcp_starts() {
+            spawn_mcp_server(&tx, name, generation, config);
+        }
+
+        if let Some(request) = app.poll_token_count() {
+            if let Some(client) = app.api_client.clone() {
+                spawn_token_count(client, request, &tx);
+            }
+        }
+
         // Wait for events with timeout for responsive UI
-        match tokio::time::timeout(std::time::Duration::from_millis(50), rx.recv()).await {
-            Ok(Some(event)) => match event {
-                AppEvent::Key(key) => {
-                    if key.kind != KeyEventKind::Press {
+        match tokio::time::timeout(Duration::from_millis(50), rx.recv()).await {
+            Ok(Some(event)) => {
+                if let Some(request) = event.request() {
+                    // A cancelled or replaced request can still have events queued
+                    if !app.is_current_request(request) {
                         continue;
                     }
-
-                    match app.mode {
-                        Mode::Help => {
-                            // Any key closes help
-                            app.mode = Mode::Normal;
+                    // Anything but another retry means the retried request is underway
+                    if !matches!(event, AppEvent::StreamRetry(..)) {
+                        app.retries.remove(&request.conversation_id);
+                    }
+                }
+                match event {
+                    AppEvent::Key(key) => {
+                        if key.kind != KeyEventKind::Press {
+                            continue;
                         }
-                        Mode::Normal => handle_normal_mode(app, key.code, key.modifiers),
-                        Mode::Insert => {
-                            if let Some(should_send) =
-                                handle_insert_mode(app, key.code, &api_client)
-                            {
-                                if should_send {
-                                    // Send API request with streaming
-                                    if let Some(ref client) = api_client {
-                                        // Apply pending model change
-                                        if let Some(new_model) = app.pending_model_change.take() {
-                                            app.current_model = Some(new_model);
-                                        }
-
-                                        app.is_loading = true;
-                                        app.start_assistant_message();
-
-                                        let client = Arc::clone(client);
-                                        let conv = app.current_conversation();
-                                        let messages: Vec<Message> =
-                                            conv.messages[..conv.messages.len() - 1].to_vec();
-                                        let tx = tx.clone();
-                                        let model = app.current_model.clone();
-
-                                        // Create a channel for stream chunks
-                                        let (stream_tx, mut stream_rx) = mpsc::channel::<StreamChunk>(32);
-
-                                        // Spawn the streaming request
-                                        tokio::spawn(async move {
-                                            let _ = client.send_message_streaming(&messages, None, model.as_deref(), stream_tx).await;
-                                        });
-
-                                        // Spawn a task to forward stream chunks to main event loop
-                                        let tx_stream = tx.clone();
-                                        tokio::spawn(async move {
-                                            while let Some(chunk) = stream_rx.recv().await {
-                                                let event = match chunk {
-                                                    StreamChunk::Text(text) => AppEvent::StreamChunk(text),
-                                                    StreamChunk::Done => AppEvent::StreamDone,
-                                                    StreamChunk::Error(e) => AppEvent::StreamError(e),
-                                                };
-                                                if tx_stream.send(event).await.is_err() {
-                                                    break;
-                                                }
-                                            }
-                                        });
+
+                        match app.mode {
+                            Mode::Help => {
+                                // Any key closes help
+                                app.mode = Mode::Normal;
+                            }
+                            Mode::Browse => handle_browse_mode(app, key.code),
+                            Mode::Mcp => handle_mcp_mode(app, key.code),
+                            Mode::Approve => {
+                                if let Some((id, step)) = handle_approve_mode(app, key.code) {
+                                    advance_tools(app, &tx, id, step);
+                                }
+                            }
+                            Mode::Normal => handle_normal_mode(app, key),
+                            Mode::Insert => {
+                                if let Some(kind) = handle_insert_mode(app, key) {
+                                    // The client is cloned per request so a config
+                                    // reload can swap it at any time
+                                    if let Some(client) = app.api_client.clone() {
+                                        let id = app.current_conversation().id;
+                                        spawn_request(app, &client, &tx, id, kind);
                                     }
                                 }
                             }
                         }
                     }
+                    AppEvent::Paste(text) => {
+                        app.paste(&text);
+                    }
+                    AppEvent::StreamChunk(request, text) => {
+                        app.append_to_message(request.conversation_id, &text);
+                    }
+                    AppEvent::StreamRetry(request, retry) => {
+                        app.schedule_retry(request.conversation_id, retry);
+                    }
+                    AppEvent::StreamUsage(request, usage) => {
+                        app.update_usage(request.conversation_id, usage);
+                    }
+                    AppEvent::TokenCount(fingerprint, tokens) => {
+                        app.set_token_count(fingerprint, tokens);
+                    }
+                    AppEvent::StreamThinkingStart(request, block) => {
+                        app.start_thinking_block(request.conversation_id, block);
+                    }
+                    AppEvent::StreamThinking(request, text) => {
+                        app.append_thinking(request.conversation_id, &text);
+                    }
+                    AppEvent::StreamSignature(request, signature) => {
+                        app.set_thinking_signature(request.conversation_id, signature);
+                    }
+                    AppEvent::StreamToolUse(request, call) => {
+                        app.add_tool_call(request.conversation_id, call);
+                    }
+                    AppEvent::ToolResult(request, tool_use_id, result) => {
+                        let id = request.conversation_id;
+                        app.set_tool_result(id, &tool_use_id, result);
+                        let step = app.next_tool_step(id);
+                        advance_tools(app, &tx, id, step);
+                    }
+                    AppEvent::McpStarted(name, generation, result) => {
+                        app.mcp_started(&name, generation, result);
+                    }
+                    AppEvent::McpExited(name, generation) => {
+                        app.mcp_exited(&name, generation);
+                    }
+                    AppEvent::StreamStop(request, reason) => {
+                        app.set_stop_reason(request.conversation_id, reason);
+                    }
+                    AppEvent::StreamDone(request) => {
+                        let id = request.conversation_id;
+                        if app.finish_streaming(id) {
+                            let step = app.next_tool_step(id);
+                            advance_tools(app, &tx, id, step);
+                        }
+                    }
+                    AppEvent::StreamError(request, error) => {
+                        app.fail_streaming(request.conversation_id, error);
+                    }
                 }
-                AppEvent::StreamChunk(text) => {
-                    app.append_to_last_message(&text);
-                }
-                AppEvent::StreamDone => {
-                    app.finish_streaming();
-                }
-                AppEvent::StreamError(error) => {
-                    app.set_error(error);
-                }
-            },
+            }
             Ok(None) => break, // Channel closed
             Err(_) => {}       // Timeout, continue
         }
@@ -171,76 +296,396 @@ async fn run(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut Ap
     Ok(())
 }
 
-fn handle_normal_mode(app: &mut App, code: KeyCode, modifiers: KeyModifiers) {
+/// Suspends the TUI, opens `$VISUAL` or `$EDITOR` on a temp file seeded with
+/// `initial`, and returns the edited text once the editor exits.
+async fn edit_in_editor(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    input_paused: &AtomicBool,
+    initial: &str,
+) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().ok_or_else(|| anyhow!("$EDITOR is empty"))?;
+    let args: Vec<&str> = parts.collect();
+
+    let path = std::env::temp_dir().join(format!("claude-tui-{}.md", Uuid::new_v4()));
+    std::fs::write(&path, initial)?;
+
+    // Give the reader task time to finish its current poll so it can't
+    // steal keystrokes meant for the editor
+    input_paused.store(true, Ordering::Relaxed);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    restore_terminal()?;
+
+    let status = tokio::process::Command::new(program)
+        .args(&args)
+        .arg(&path)
+        .status()
+        .await;
+
+    setup_terminal()?;
+    terminal.clear()?;
+    input_paused.store(false, Ordering::Relaxed);
+
+    let result = match status {
+        Ok(status) if status.success() => std::fs::read_to_string(&path).map_err(Into::into),
+        Ok(status) => Err(anyhow!("{} exited with {}", program, status)),
+        Err(e) => Err(anyhow!("could not run {}: {}", program, e)),
+    };
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+/// Sends a conversation to the API with streaming. Every event produced by
+/// the request is tagged with the conversation id so chunks land in the
+/// right tab regardless of which one is active when they arrive.
+fn spawn_request(
+    app: &mut App,
+    client: &Arc<ApiClient>,
+    tx: &mpsc::Sender<AppEvent>,
+    conversation_id: Uuid,
+    kind: RequestKind,
+) {
+    // Apply pending model change
+    if let Some(new_model) = app.pending_model_change.take() {
+        app.current_model = Some(new_model);
+    }
+
+    let Some(conv) = app.conversation(conversation_id) else {
+        return;
+    };
+    let messages: Vec<Message> = conv.messages.clone();
+    let system_prompt = conv.system_prompt.clone();
+    // A continuation sends the partial reply as the final assistant turn,
+    // which the model picks up from
+    match kind {
+        RequestKind::Reply => app.start_assistant_message(conversation_id),
+        RequestKind::Continue => app.resume_assistant_message(conversation_id),
+    }
+
+    let client = Arc::clone(client);
+    let model = app.current_model.clone();
+    let thinking_budget = app.thinking_budget;
+    let tools = app.tools.definitions();
+
+    // Create a channel for stream chunks
+    let (stream_tx, mut stream_rx) = mpsc::channel::<StreamChunk>(32);
+
+    let request = async move {
+        // Connection failures never reach the stream, so report them here
+        let error_tx = stream_tx.clone();
+        if let Err(e) = client
+            .send_message_streaming(
+                &messages,
+                system_prompt.as_deref(),
+                model.as_deref(),
+                thinking_budget,
+                &tools,
+                stream_tx,
+            )
+            .await
+        {
+            let _ = error_tx.send(StreamChunk::Error(e)).await;
+        }
+    };
+
+    // Forward stream chunks to main event loop
+    let id = app.new_request_id(conversation_id);
+    let tx_stream = tx.clone();
+    let forward = async move {
+        while let Some(chunk) = stream_rx.recv().await {
+            let event = match chunk {
+                StreamChunk::Text(text) => AppEvent::StreamChunk(id, text),
+                StreamChunk::Retry(retry) => AppEvent::StreamRetry(id, retry),
+                StreamChunk::Usage(usage) => AppEvent::StreamUsage(id, usage),
+                StreamChunk::Stop(reason) => AppEvent::StreamStop(id, reason),
+                StreamChunk::ThinkingStart(block) => AppEvent::StreamThinkingStart(id, block),
+                StreamChunk::Thinking(text) => AppEvent::StreamThinking(id, text),
+                StreamChunk::Signature(sig) => AppEvent::StreamSignature(id, sig),
+                StreamChunk::ToolUse(call) => AppEvent::StreamToolUse(id, call),
+                StreamChunk::Done => AppEvent::StreamDone(id),
+                StreamChunk::Error(e) => AppEvent::StreamError(id, e),
+            };
+            if tx_stream.send(event).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    // Run both in one task so a single abort stops the request and the
+    // forwarding together
+    let handle = tokio::spawn(async move {
+        tokio::join!(request, forward);
+    });
+    app.track_request(id, handle.abort_handle());
+}
+
+/// Acts on the next step of a conversation's tool calls: runs a call, or
+/// sends the results back to the model once every call has one.
+fn advance_tools(
+    app: &mut App,
+    tx: &mpsc::Sender<AppEvent>,
+    conversation_id: Uuid,
+    step: ToolStep,
+) {
+    match step {
+        ToolStep::Run(call) => spawn_tool(app, tx, conversation_id, call),
+        ToolStep::Wait => {}
+        ToolStep::Done => match app.api_client.clone() {
+            Some(client) => spawn_request(app, &client, tx, conversation_id, RequestKind::Reply),
+            None => {
+                app.finish_streaming(conversation_id);
+            }
+        },
+    }
+}
+
+/// Runs a tool call in the background and reports its result. The task
+/// takes the conversation's request slot so cancelling stops it.
+fn spawn_tool(app: &mut App, tx: &mpsc::Sender<AppEvent>, conversation_id: Uuid, call: ToolCall) {
+    let tools = app.tools.clone();
+    let tx = tx.clone();
+    let id = app.new_request_id(conversation_id);
+    let handle = tokio::spawn(async move {
+        let result = tools.run(&call).await;
+        let _ = tx.send(AppEvent::ToolResult(id, call.id, result)).await;
+    });
+    app.track_request(id, handle.abort_handle());
+}
+
+/// Launches an MCP server in the background, then reports when it is ready
+/// and, later, when it exits.
+fn spawn_mcp_server(
+    tx: &mpsc::Sender<AppEvent>,
+    name: String,
+    generation: u64,
+    config: McpServerConfig,
+) {
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        let (exited_tx, exited_rx) = oneshot::channel();
+        let result = McpSession::start(&config, exited_tx)
+            .await
+            .map(Arc::new)
+            .map_err(|e| format!("{:#}", e));
+        let started = result.is_ok();
+        if tx
+            .send(AppEvent::McpStarted(name.clone(), generation, result))
+            .await
+            .is_err()
+            || !started
+        {
+            return;
+        }
+        let _ = exited_rx.await;
+        let _ = tx.send(AppEvent::McpExited(name, generation)).await;
+    });
+}
+
+/// Counts the tokens of the next request in the background. Failures are
+/// silent: the local estimate is shown instead.
+fn spawn_token_count(client: Arc<ApiClient>, request: CountRequest, tx: &mpsc::Sender<AppEvent>) {
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        let count = client
+            .count_tokens(
+                &request.messages,
+                request.system_prompt.as_deref(),
+                request.model.as_deref(),
+                &request.tools,
+            )
+            .await;
+        if let Ok(tokens) = count {
+            let _ = tx
+                .send(AppEvent::TokenCount(request.fingerprint, tokens))
+                .await;
+        }
+    });
+}
+
+fn handle_normal_mode(app: &mut App, key: KeyEvent) {
     app.clear_error();
 
-    // Handle Ctrl+ keybindings
-    if modifiers.contains(KeyModifiers::CONTROL) {
-        match code {
-            KeyCode::Char('n') => app.new_conversation(),
-            KeyCode::Char('w') => app.close_current_conversation(),
-            KeyCode::Char('h') | KeyCode::Left => app.prev_tab(),
-            KeyCode::Char('l') | KeyCode::Right => app.next_tab(),
-            KeyCode::Char('s') => app.save_current_conversation(),
-            _ => {}
+    if let Some(action) = app.config.keys.action(&key) {
+        match action {
+            Action::Quit => app.should_quit = true,
+            Action::Insert => app.mode = Mode::Insert,
+            Action::Help => app.toggle_help(),
+            Action::NewConversation => app.new_conversation(),
+            Action::CloseConversation => app.close_current_conversation(),
+            Action::PrevTab => app.prev_tab(),
+            Action::NextTab => app.next_tab(),
+            Action::Save => app.save_current_conversation(),
+            Action::Browse => app.open_browser(),
+            Action::Cancel => app.cancel_request(),
+            Action::ToggleRawMarkdown => app.show_raw_markdown = !app.show_raw_markdown,
+            Action::ToggleThinking => app.show_thinking = !app.show_thinking,
+            Action::EditInput => app.pending_edit = Some(EditTarget::Input),
+            Action::EditSystemPrompt => app.pending_edit = Some(EditTarget::SystemPrompt),
+            Action::ScrollDown => app.current_conversation_mut().scroll_down(1),
+            Action::ScrollUp => app.current_conversation_mut().scroll_up(1),
+            Action::HalfPageDown => app.current_conversation_mut().half_page_down(),
+            Action::HalfPageUp => app.current_conversation_mut().half_page_up(),
+            Action::PageDown => app.current_conversation_mut().page_down(),
+            Action::PageUp => app.current_conversation_mut().page_up(),
+            Action::Top => app.current_conversation_mut().scroll_to_top(),
+            Action::Bottom => app.current_conversation_mut().scroll_to_bottom(),
         }
         return;
     }
 
-    match code {
-        KeyCode::Char('q') => app.should_quit = true,
-        KeyCode::Char('i') | KeyCode::Enter => app.mode = Mode::Insert,
-        KeyCode::Char('j') | KeyCode::Down => app.current_conversation_mut().scroll_down(10),
-        KeyCode::Char('k') | KeyCode::Up => app.current_conversation_mut().scroll_up(),
-        KeyCode::Char('g') => app.current_conversation_mut().scroll_to_top(),
-        KeyCode::Char('G') => app.current_conversation_mut().scroll_to_bottom(10),
-        KeyCode::Char('?') => app.toggle_help(),
+    // Fixed keys that work regardless of the configured bindings
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Left if ctrl => app.prev_tab(),
+        KeyCode::Right if ctrl => app.next_tab(),
+        KeyCode::Esc => app.cancel_request(),
+        KeyCode::Enter => app.mode = Mode::Insert,
+        KeyCode::Down => app.current_conversation_mut().scroll_down(1),
+        KeyCode::Up => app.current_conversation_mut().scroll_up(1),
+        KeyCode::PageDown => app.current_conversation_mut().page_down(),
+        KeyCode::PageUp => app.current_conversation_mut().page_up(),
+        KeyCode::Home => app.current_conversation_mut().scroll_to_top(),
+        KeyCode::End => app.current_conversation_mut().scroll_to_bottom(),
         _ => {}
     }
 }
 
-fn handle_insert_mode(
-    app: &mut App,
-    code: KeyCode,
-    api_client: &Option<Arc<ApiClient>>,
-) -> Option<bool> {
+/// Answers the approval prompt for a tool call. Allowing a tool for good
+/// takes a shifted key.
+fn handle_approve_mode(app: &mut App, code: KeyCode) -> Option<(Uuid, ToolStep)> {
+    if !app.approval_ready() {
+        return None;
+    }
+    let choice = match code {
+        KeyCode::Char('y') => ApprovalChoice::Once,
+        KeyCode::Char('Y') => ApprovalChoice::Always,
+        KeyCode::Char('n') | KeyCode::Esc => ApprovalChoice::Deny,
+        _ => return None,
+    };
+    app.answer_approval(choice)
+}
+
+fn handle_mcp_mode(app: &mut App, code: KeyCode) {
     match code {
-        KeyCode::Esc => {
-            app.mode = Mode::Normal;
-            None
+        KeyCode::Esc | KeyCode::Char('q') => app.mode = Mode::Normal,
+        KeyCode::Char('j') | KeyCode::Down => app.select_next_mcp(),
+        KeyCode::Char('k') | KeyCode::Up => app.select_prev_mcp(),
+        KeyCode::Char('r') => {
+            if let Some(name) = app
+                .mcp_servers
+                .get(app.mcp_selected)
+                .map(|s| s.name.clone())
+            {
+                app.restart_mcp(Some(&name));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn handle_browse_mode(app: &mut App, code: KeyCode) {
+    let Some(browser) = app.browser.as_mut() else {
+        app.mode = Mode::Normal;
+        return;
+    };
+
+    match browser.input.clone() {
+        BrowseInput::Filter => match code {
+            KeyCode::Esc => {
+                browser.filter.clear();
+                browser.input = BrowseInput::None;
+            }
+            KeyCode::Enter => browser.input = BrowseInput::None,
+            KeyCode::Backspace => browser.pop_filter(),
+            KeyCode::Down => browser.select_next(),
+            KeyCode::Up => browser.select_prev(),
+            KeyCode::Char(c) => browser.push_filter(c),
+            _ => {}
+        },
+        BrowseInput::Rename(mut title) => match code {
+            KeyCode::Esc => browser.input = BrowseInput::None,
+            KeyCode::Enter => app.finish_rename(),
+            KeyCode::Backspace => {
+                title.pop();
+                browser.input = BrowseInput::Rename(title);
+            }
+            KeyCode::Char(c) => {
+                title.push(c);
+                browser.input = BrowseInput::Rename(title);
+            }
+            _ => {}
+        },
+        BrowseInput::ConfirmDelete => match code {
+            KeyCode::Char('y') => app.delete_selected_conversation(),
+            _ => browser.input = BrowseInput::None,
+        },
+        BrowseInput::None => match code {
+            KeyCode::Esc | KeyCode::Char('q') => app.close_browser(),
+            KeyCode::Char('j') | KeyCode::Down => browser.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => browser.select_prev(),
+            KeyCode::Char('/') => browser.input = BrowseInput::Filter,
+            KeyCode::Char('s') => browser.cycle_sort(),
+            KeyCode::Char('r') => app.start_rename(),
+            KeyCode::Char('d') => browser.input = BrowseInput::ConfirmDelete,
+            KeyCode::Enter => app.open_selected_conversation(),
+            _ => {}
+        },
+    }
+}
+
+fn handle_insert_mode(app: &mut App, key: KeyEvent) -> Option<RequestKind> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+
+    match key.code {
+        KeyCode::Esc => app.mode = Mode::Normal,
+        KeyCode::Enter
+            if key
+                .modifiers
+                .intersects(KeyModifiers::SHIFT | KeyModifiers::ALT) =>
+        {
+            app.insert_char('\n');
         }
         KeyCode::Enter => {
-            if app.is_loading {
+            if app.is_loading() {
                 return None;
             }
 
-            if api_client.is_none() {
+            if !app.has_api_key() {
                 app.set_error("ANTHROPIC_API_KEY not set".to_string());
                 return None;
             }
 
-            if app.submit().is_some() {
-                Some(true) // Signal to send API request
-            } else {
-                None
-            }
-        }
-        KeyCode::Backspace => {
-            app.delete_char();
-            None
-        }
-        KeyCode::Left => {
-            app.move_cursor_left();
-            None
+            return app.submit();
         }
-        KeyCode::Right => {
-            app.move_cursor_right();
-            None
-        }
-        KeyCode::Char(c) => {
-            app.insert_char(c);
-            None
-        }
-        _ => None,
+        KeyCode::Backspace if ctrl || alt => app.delete_word(),
+        KeyCode::Backspace => app.delete_char(),
+        KeyCode::Delete if ctrl || alt => app.delete_word_forward(),
+        KeyCode::Delete => app.delete_char_forward(),
+        KeyCode::Left if ctrl || alt => app.move_cursor_word_left(),
+        KeyCode::Right if ctrl || alt => app.move_cursor_word_right(),
+        KeyCode::Left => app.move_cursor_left(),
+        KeyCode::Right => app.move_cursor_right(),
+        KeyCode::Up => app.move_cursor_up(),
+        KeyCode::Down => app.move_cursor_down(),
+        KeyCode::Home => app.move_cursor_line_start(),
+        KeyCode::End => app.move_cursor_line_end(),
+        KeyCode::Char('c') if ctrl => app.cancel_request(),
+        KeyCode::Char('g') if ctrl => app.pending_edit = Some(EditTarget::Input),
+        KeyCode::Char('w') if ctrl => app.delete_word(),
+        KeyCode::Char('b') if alt => app.move_cursor_word_left(),
+        KeyCode::Char('f') if alt => app.move_cursor_word_right(),
+        KeyCode::Char('d') if alt => app.delete_word_forward(),
+        KeyCode::Char('a') if ctrl => app.move_cursor_line_start(),
+        KeyCode::Char('e') if ctrl => app.move_cursor_line_end(),
+        // AltGr arrives as Ctrl+Alt on Windows and some terminals, so a
+        // character with both held is typed text like `@` or `€`
+        KeyCode::Char(_) if ctrl != alt => {}
+        KeyCode::Char(c) => app.insert_char(c),
+        _ => {}
     }
+    None
 }
@@ -0,0 +1,580 @@
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::highlight;
+use crate::text;
+
+/// Renders markdown into styled lines already wrapped to `width` columns.
+pub fn render(text: &str, width: usize) -> Vec<Line<'static>> {
+    let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut renderer = Renderer::new(width.max(1));
+
+    for event in Parser::new_ext(text, options) {
+        renderer.handle(event);
+    }
+    renderer.flush();
+
+    if renderer.lines.is_empty() {
+        renderer.lines.push(Line::from(""));
+    }
+    renderer.lines
+}
+
+struct Renderer {
+    lines: Vec<Line<'static>>,
+    width: usize,
+    /// Inline style stack; the top is applied to incoming text.
+    styles: Vec<Style>,
+    /// Spans of the logical line being built, wrapped on flush.
+    current: Vec<Span<'static>>,
+    /// One entry per open list: the next number for ordered lists.
+    lists: Vec<Option<u64>>,
+    /// Marker of the list item whose first line hasn't been flushed yet.
+    item_marker: Option<String>,
+    marker_width: usize,
+    quote_depth: usize,
+    /// Language tag and accumulated source of the open fenced code block.
+    code_block: Option<(String, String)>,
+    pending_blank: bool,
+}
+
+impl Renderer {
+    fn new(width: usize) -> Self {
+        Self {
+            lines: Vec::new(),
+            width,
+            styles: vec![Style::default()],
+            current: Vec::new(),
+            lists: Vec::new(),
+            item_marker: None,
+            marker_width: 0,
+            quote_depth: 0,
+            code_block: None,
+            pending_blank: false,
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => match self.code_block.as_mut() {
+                Some((_, code)) => code.push_str(&text),
+                None => self.push_text(&text),
+            },
+            Event::Code(code) => {
+                self.current
+                    .push(Span::styled(code.to_string(), code_style()));
+            }
+            Event::SoftBreak => self.push_text(" "),
+            Event::HardBreak => self.flush(),
+            Event::Rule => {
+                self.start_block();
+                let rule = "─".repeat(self.width);
+                self.lines.push(Line::from(Span::styled(
+                    rule,
+                    Style::default().fg(Color::DarkGray),
+                )));
+                self.pending_blank = true;
+            }
+            Event::TaskListMarker(checked) => {
+                self.push_text(if checked { "[x] " } else { "[ ] " });
+            }
+            Event::Html(html) | Event::InlineHtml(html) => self.push_text(&html),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.start_block(),
+            Tag::Heading { level, .. } => {
+                self.start_block();
+                self.push_style(heading_style(level));
+            }
+            Tag::BlockQuote(_) => {
+                self.start_block();
+                self.quote_depth += 1;
+            }
+            Tag::CodeBlock(kind) => {
+                self.start_block();
+                let lang = match kind {
+                    // The info string may carry attributes after the language
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code_block = Some((lang, String::new()));
+            }
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.start_block();
+                } else {
+                    self.flush();
+                }
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.flush();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        let marker = format!("{}. ", n);
+                        *n += 1;
+                        marker
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.marker_width = text::display_width(&marker);
+                self.item_marker = Some(marker);
+            }
+            Tag::Emphasis => self.push_style(Style::default().add_modifier(Modifier::ITALIC)),
+            Tag::Strong => self.push_style(Style::default().add_modifier(Modifier::BOLD)),
+            Tag::Strikethrough => {
+                self.push_style(Style::default().add_modifier(Modifier::CROSSED_OUT))
+            }
+            Tag::Link { .. } => self.push_style(
+                Style::default()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::UNDERLINED),
+            ),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph => {
+                self.flush();
+                self.pending_blank = true;
+            }
+            TagEnd::Heading(_) => {
+                self.flush();
+                self.styles.pop();
+                self.pending_blank = true;
+            }
+            TagEnd::BlockQuote(_) => {
+                self.flush();
+                self.quote_depth -= 1;
+                self.pending_blank = true;
+            }
+            TagEnd::CodeBlock => {
+                if let Some((lang, code)) = self.code_block.take() {
+                    self.render_code_block(&lang, &code);
+                }
+                self.pending_blank = true;
+            }
+            TagEnd::List(_) => {
+                self.flush();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.pending_blank = true;
+                }
+            }
+            TagEnd::Item => self.flush(),
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link => {
+                self.styles.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn push_style(&mut self, style: Style) {
+        let current = *self.styles.last().unwrap_or(&Style::default());
+        self.styles.push(current.patch(style));
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let style = *self.styles.last().unwrap_or(&Style::default());
+        self.current.push(Span::styled(text.to_string(), style));
+    }
+
+    /// Flushes the current line and separates the new block from the
+    /// previous one with a blank line.
+    fn start_block(&mut self) {
+        self.flush();
+        if self.pending_blank && !self.lines.is_empty() {
+            self.lines.push(Line::from(""));
+        }
+        self.pending_blank = false;
+    }
+
+    /// Prefix for a line: blockquote bars, list indentation and, on the
+    /// first line of a list item, its marker.
+    fn prefix(&mut self, first: bool) -> Vec<Span<'static>> {
+        let mut prefix = Vec::new();
+        if self.quote_depth > 0 {
+            prefix.push(Span::styled(
+                "│ ".repeat(self.quote_depth),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        if !self.lists.is_empty() {
+            prefix.push(Span::raw("  ".repeat(self.lists.len() - 1)));
+            match self.item_marker.take().filter(|_| first) {
+                Some(marker) => {
+                    prefix.push(Span::styled(marker, Style::default().fg(Color::Yellow)))
+                }
+                None => prefix.push(Span::raw(" ".repeat(self.marker_width))),
+            }
+        }
+        prefix
+    }
+
+    fn flush(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+        let spans = std::mem::take(&mut self.current);
+
+        let first_prefix = self.prefix(true);
+        let rest_prefix = self.prefix(false);
+        let prefix_width = spans_width(&first_prefix);
+        let available = self.width.saturating_sub(prefix_width).max(1);
+
+        let wrapped = wrap_spans(spans, available);
+
+        for (i, line) in wrapped.into_iter().enumerate() {
+            let mut full = if i == 0 {
+                first_prefix.clone()
+            } else {
+                rest_prefix.clone()
+            };
+            full.extend(line);
+            self.lines.push(Line::from(full));
+        }
+    }
+
+    /// Draws a fenced code block inside a frame labelled with its language.
+    /// Lines are never reflowed: long lines are soft-wrapped at the frame
+    /// edge and continue at the same indentation.
+    fn render_code_block(&mut self, lang: &str, code: &str) {
+        let code = code.strip_suffix('\n').unwrap_or(code);
+        let highlighted = highlight::highlight(code, lang).unwrap_or_else(|| {
+            code.lines()
+                .map(|line| vec![Span::styled(line.to_string(), code_style())])
+                .collect()
+        });
+
+        let prefix = self.prefix(true);
+        let frame_style = Style::default().fg(Color::DarkGray);
+        let available = self.width.saturating_sub(spans_width(&prefix));
+        // "│ " on the left; the frame is only as wide as the longest line
+        let code_width = available.saturating_sub(2).max(1);
+        let longest = highlighted
+            .iter()
+            .map(|l| spans_width(l))
+            .max()
+            .unwrap_or(0);
+        let label_width = text::display_width(lang) + 4;
+        let frame_width = (longest + 2).max(label_width).min(available);
+
+        let label = if lang.is_empty() {
+            String::new()
+        } else {
+            format!(" {} ", lang)
+        };
+        let top_fill = frame_width.saturating_sub(text::display_width(&label) + 2);
+        let mut top = prefix.clone();
+        top.push(Span::styled("╭─", frame_style));
+        top.push(Span::styled(
+            label,
+            frame_style.add_modifier(Modifier::ITALIC),
+        ));
+        top.push(Span::styled("─".repeat(top_fill), frame_style));
+        self.lines.push(Line::from(top));
+
+        for line in highlighted {
+            for segment in soft_wrap(line, code_width) {
+                let mut full = prefix.clone();
+                full.push(Span::styled("│ ", frame_style));
+                full.extend(segment);
+                self.lines.push(Line::from(full));
+            }
+        }
+
+        let mut bottom = prefix;
+        bottom.push(Span::styled(
+            format!("╰{}", "─".repeat(frame_width.saturating_sub(1))),
+            frame_style,
+        ));
+        self.lines.push(Line::from(bottom));
+    }
+}
+
+fn heading_style(level: HeadingLevel) -> Style {
+    let style = Style::default().add_modifier(Modifier::BOLD);
+    match level {
+        HeadingLevel::H1 => style.fg(Color::Cyan).add_modifier(Modifier::UNDERLINED),
+        HeadingLevel::H2 => style.fg(Color::Cyan),
+        _ => style,
+    }
+}
+
+fn code_style() -> Style {
+    Style::default().fg(Color::Yellow)
+}
+
+fn spans_width(spans: &[Span]) -> usize {
+    spans.iter().map(|s| text::display_width(&s.content)).sum()
+}
+
+/// Greedy word wrap over styled spans. Whitespace at a break is dropped and
+/// words longer than the line are split.
+fn wrap_spans(spans: Vec<Span<'static>>, width: usize) -> Vec<Vec<Span<'static>>> {
+    let mut lines: Vec<Vec<Span<'static>>> = Vec::new();
+    let mut line: Vec<Span<'static>> = Vec::new();
+    let mut line_width = 0;
+
+    for span in spans {
+        let style = span.style;
+        for token in split_words(&span.content) {
+            let token_width = text::display_width(&token);
+
+            if token.chars().all(char::is_whitespace) {
+                if line_width > 0 && line_width + token_width <= width {
+                    line.push(Span::styled(token, style));
+                    line_width += token_width;
+                }
+                continue;
+            }
+
+            if line_width + token_width > width && line_width > 0 {
+                trim_trailing_space(&mut line);
+                lines.push(std::mem::take(&mut line));
+                line_width = 0;
+            }
+
+            let mut remaining = token.as_str();
+            while text::display_width(remaining) > width {
+                let (head, tail) = text::split_at_width(remaining, width);
+                lines.push(vec![Span::styled(head.to_string(), style)]);
+                remaining = tail;
+            }
+            line_width += text::display_width(remaining);
+            line.push(Span::styled(remaining.to_string(), style));
+        }
+    }
+
+    trim_trailing_space(&mut line);
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn trim_trailing_space(line: &mut Vec<Span<'static>>) {
+    while let Some(last) = line.last() {
+        if last.content.chars().all(char::is_whitespace) {
+            line.pop();
+        } else {
+            break;
+        }
+    }
+}
+
+/// Splits a line of styled spans into segments of at most `width` columns.
+/// Continuation segments repeat the line's leading whitespace so wrapped
+/// code stays visually aligned with its block.
+fn soft_wrap(spans: Vec<Span<'static>>, width: usize) -> Vec<Vec<Span<'static>>> {
+    if spans_width(&spans) <= width {
+        return vec![spans];
+    }
+
+    let text: String = spans.iter().map(|s| s.content.as_ref()).collect();
+    let indent: String = text.chars().take_while(|c| c.is_whitespace()).collect();
+    let indent = if text::display_width(&indent) < width / 2 {
+        indent
+    } else {
+        String::new()
+    };
+
+    let mut segments = Vec::new();
+    let mut segment: Vec<Span<'static>> = Vec::new();
+    let mut segment_width = 0;
+
+    for span in spans {
+        let style = span.style;
+        let mut chunk = String::new();
+        for g in span.content.graphemes(true) {
+            let g_width = text::display_width(g);
+            if segment_width + g_width > width && segment_width > 0 {
+                if !chunk.is_empty() {
+                    segment.push(Span::styled(std::mem::take(&mut chunk), style));
+                }
+                segments.push(std::mem::take(&mut segment));
+                segment.push(Span::raw(indent.clone()));
+                segment_width = text::display_width(&indent);
+            }
+            chunk.push_str(g);
+            segment_width += g_width;
+        }
+        if !chunk.is_empty() {
+            segment.push(Span::styled(chunk, style));
+        }
+    }
+    if !segment.is_empty() {
+        segments.push(segment);
+    }
+    segments
+}
+
+/// Splits text into alternating runs of whitespace and non-whitespace.
+fn split_words(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_space = false;
+
+    for c in text.chars() {
+        if c.is_whitespace() != in_space && !current.is_empty() {
+            tokens.push(std::mem::take(&mut current));
+        }
+        in_space = c.is_whitespace();
+        current.push(c);
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(lines: &[Line]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| line.spans.iter().map(|s| s.content.as_ref()).collect())
+            .collect()
+    }
+
+    fn render_plain(text: &str, width: usize) -> Vec<String> {
+        plain(&render(text, width))
+    }
+
+    /// The span holding `text` in the rendered output.
+    fn span<'a>(lines: &'a [Line<'static>], text: &str) -> &'a Span<'static> {
+        lines
+            .iter()
+            .flat_map(|line| &line.spans)
+            .find(|s| s.content == text)
+            .unwrap_or_else(|| panic!("no span {:?}", text))
+    }
+
+    #[test]
+    fn empty_input_renders_one_blank_line() {
+        assert_eq!(render_plain("", 20), [""]);
+    }
+
+    #[test]
+    fn headings_are_styled_and_set_apart() {
+        let lines = render("# Title\nBody text", 20);
+        assert_eq!(plain(&lines), ["Title", "", "Body text"]);
+        assert_eq!(span(&lines, "Title").style, heading_style(HeadingLevel::H1));
+        assert_eq!(span(&lines, "Body").style, Style::default());
+    }
+
+    #[test]
+    fn paragraphs_wrap_at_word_boundaries() {
+        assert_eq!(
+            render_plain("one two three four\n\nnext", 9),
+            ["one two", "three", "four", "", "next"]
+        );
+        assert_eq!(render_plain("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn inline_styles_and_code() {
+        let lines = render("Run `cargo` with *care* and **speed**", 80);
+        assert_eq!(plain(&lines), ["Run cargo with care and speed"]);
+        assert_eq!(span(&lines, "cargo").style, code_style());
+        assert!(span(&lines, "care")
+            .style
+            .add_modifier
+            .contains(Modifier::ITALIC));
+        assert!(span(&lines, "speed")
+            .style
+            .add_modifier
+            .contains(Modifier::BOLD));
+    }
+
+    #[test]
+    fn lists_get_markers_and_hanging_indents() {
+        assert_eq!(render_plain("- one\n- two", 20), ["• one", "• two"]);
+        assert_eq!(
+            render_plain("3. three\n4. four", 20),
+            ["3. three", "4. four"]
+        );
+        assert_eq!(
+            render_plain("- outer\n  - inner", 20),
+            ["• outer", "  • inner"]
+        );
+        assert_eq!(
+            render_plain("- aaa bbb ccc", 8),
+            ["• aaa", "  bbb", "  ccc"]
+        );
+        assert_eq!(render_plain("- [x] done", 20), ["• [x] done"]);
+    }
+
+    #[test]
+    fn quotes_and_rules() {
+        assert_eq!(render_plain("> quoted", 20), ["│ quoted"]);
+        assert_eq!(
+            render_plain("above\n\n---\n\nbelow", 5),
+            ["above", "", "─────", "", "below"]
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_framed_and_labelled() {
+        let lines = render("```rust\nfn main() {}\n```", 40);
+        assert_eq!(
+            plain(&lines),
+            ["╭─ rust ──────", "│ fn main() {}", "╰─────────────"]
+        );
+        // Highlighted rather than drawn in the plain code style
+        assert!(lines[1]
+            .spans
+            .iter()
+            .skip(1)
+            .all(|s| matches!(s.style.fg, Some(Color::Rgb(..)))));
+    }
+
+    #[test]
+    fn code_blocks_in_unknown_languages_use_the_code_style() {
+        let lines = render("```\nx  =  1\n```", 40);
+        assert_eq!(plain(&lines), ["╭────────", "│ x  =  1", "╰────────"]);
+        assert_eq!(lines[1].spans[1].style, code_style());
+    }
+
+    #[test]
+    fn long_code_lines_soft_wrap_at_their_indentation() {
+        let lines = render("```\n    let value = 1234567890;\n```", 16);
+        assert_eq!(
+            plain(&lines),
+            [
+                "╭───────────────",
+                "│     let value ",
+                "│     = 12345678",
+                "│     90;",
+                "╰───────────────",
+            ]
+        );
+        assert!(lines.iter().all(|line| line.width() <= 16));
+    }
+
+    #[test]
+    fn soft_wrap_drops_indentation_that_would_fill_the_line() {
+        let spans = vec![Span::raw("      abcdefgh")];
+        let segments: Vec<String> = soft_wrap(spans, 8)
+            .iter()
+            .map(|s| s.iter().map(|s| s.content.as_ref()).collect())
+            .collect();
+        assert_eq!(segments, ["      ab", "cdefgh"]);
+    }
+}
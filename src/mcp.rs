@@ -0,0 +1,472 @@
+use std::collections::{HashMap, VecDeque};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::oneshot;
+
+use crate::config::McpServerConfig;
+use crate::tools::{truncate_output, Tool, ToolFuture};
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+/// How long the handshake and listing may take before the server counts as
+/// failed.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a tool call may run before it is reported as failed.
+const CALL_TIMEOUT: Duration = Duration::from_secs(120);
+/// Lines of the server's stderr kept for `/mcp`.
+const STDERR_LINES: usize = 20;
+
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Requests waiting for a response, by id. `None` once the server has
+/// exited.
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Result<Value, String>>>>>>;
+/// Where the result of one request, or the server's error message, arrives.
+type Response = oneshot::Receiver<Result<Value, String>>;
+
+#[derive(Clone, Deserialize)]
+pub struct McpTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename = "inputSchema", default = "empty_schema")]
+    pub input_schema: Value,
+}
+
+fn empty_schema() -> Value {
+    json!({ "type": "object" })
+}
+
+#[derive(Clone, Deserialize)]
+pub struct McpResource {
+    pub uri: String,
+    pub name: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct McpPrompt {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ServerInfo {
+    name: String,
+    #[serde(default)]
+    version: String,
+}
+
+/// A running MCP server, spoken to with newline-delimited JSON-RPC over its
+/// stdin and stdout.
+pub struct McpSession {
+    /// Name and version the server reported.
+    pub server_info: Option<String>,
+    pub tools: Vec<McpTool>,
+    pub resources: Vec<McpResource>,
+    pub prompts: Vec<McpPrompt>,
+    /// The last lines the server wrote to stderr.
+    pub stderr: Arc<Mutex<VecDeque<String>>>,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Pending,
+    next_id: AtomicU64,
+    child: Mutex<Child>,
+}
+
+impl McpSession {
+    /// Launches the server, performs the handshake and lists what it
+    /// offers. `exited` fires once the server's stdout closes.
+    pub async fn start(config: &McpServerConfig, exited: oneshot::Sender<()>) -> Result<Self> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("could not run {}", config.command))?;
+
+        let stdin = Arc::new(tokio::sync::Mutex::new(
+            child.stdin.take().ok_or_else(|| anyhow!("no stdin"))?,
+        ));
+        let stdout = child.stdout.take().ok_or_else(|| anyhow!("no stdout"))?;
+        let stderr_pipe = child.stderr.take().ok_or_else(|| anyhow!("no stderr"))?;
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let stderr: Arc<Mutex<VecDeque<String>>> = Arc::default();
+
+        tokio::spawn(read_messages(
+            stdout,
+            stdin.clone(),
+            pending.clone(),
+            exited,
+        ));
+        let tail = stderr.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr_pipe).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let mut tail = tail.lock().unwrap_or_else(|e| e.into_inner());
+                if tail.len() == STDERR_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+        });
+
+        let mut session = Self {
+            server_info: None,
+            tools: Vec::new(),
+            resources: Vec::new(),
+            prompts: Vec::new(),
+            stderr,
+            stdin,
+            pending,
+            next_id: AtomicU64::new(1),
+            child: Mutex::new(child),
+        };
+        match tokio::time::timeout(STARTUP_TIMEOUT, session.initialize()).await {
+            Ok(Ok(())) => Ok(session),
+            Ok(Err(e)) => Err(session.with_stderr(e)),
+            Err(_) => {
+                Err(session
+                    .with_stderr(anyhow!("no response within {}s", STARTUP_TIMEOUT.as_secs())))
+            }
+        }
+    }
+
+    async fn initialize(&mut self) -> Result<()> {
+        let result = self
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": { "name": "claude-tui", "version": env!("CARGO_PKG_VERSION") },
+                }),
+            )
+            .await?;
+        self.notify("notifications/initialized", json!({})).await?;
+
+        if let Ok(info) = serde_json::from_value::<ServerInfo>(result["serverInfo"].clone()) {
+            self.server_info = Some(format!("{} {}", info.name, info.version).trim().to_string());
+        }
+        // Only ask for what the server says it supports
+        let capabilities = &result["capabilities"];
+        if capabilities.get("tools").is_some() {
+            self.tools = self.list("tools/list", "tools").await?;
+        }
+        if capabilities.get("resources").is_some() {
+            self.resources = self.list("resources/list", "resources").await?;
+        }
+        if capabilities.get("prompts").is_some() {
+            self.prompts = self.list("prompts/list", "prompts").await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches every page of a list.
+    async fn list<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        field: &str,
+    ) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let mut result = self.request(method, params).await?;
+            let page: Vec<T> = serde_json::from_value(result[field].take())
+                .with_context(|| format!("{}: unexpected response", method))?;
+            items.extend(page);
+            cursor = result["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+
+    /// Runs a tool and returns its text content, truncated like the
+    /// built-in tools' output. A result the server flags as an error, or no
+    /// result in time, becomes an `Err`.
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<String> {
+        let method = "tools/call";
+        let (id, response) = self
+            .send_request(method, json!({ "name": name, "arguments": arguments }))
+            .await?;
+        let Ok(response) = tokio::time::timeout(CALL_TIMEOUT, response).await else {
+            // A late response now finds no waiter, and the server is told it
+            // can stop working on the call.
+            self.forget(id);
+            let _ = self
+                .notify(
+                    "notifications/cancelled",
+                    json!({ "requestId": id, "reason": "timed out" }),
+                )
+                .await;
+            bail!("{}: no response within {}s", name, CALL_TIMEOUT.as_secs());
+        };
+        let result = read_response(method, response)?;
+        let text: Vec<String> = result["content"]
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .map(|item| match item["type"].as_str() {
+                        Some("text") => item["text"].as_str().unwrap_or_default().to_string(),
+                        Some(kind) => format!("[{} content]", kind),
+                        None => item.to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let text = truncate_output(text.join("\n"));
+        if result["isError"].as_bool().unwrap_or(false) {
+            bail!(text);
+        }
+        Ok(text)
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let (_, response) = self.send_request(method, params).await?;
+        read_response(method, response.await)
+    }
+
+    /// Writes a request and returns its id and where its response will
+    /// arrive.
+    async fn send_request(&self, method: &str, params: Value) -> Result<(u64, Response)> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        match self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+        {
+            Some(pending) => pending.insert(id, tx),
+            None => bail!("{}: server exited", method),
+        };
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if let Err(e) = write_message(&self.stdin, &message).await {
+            self.forget(id);
+            return Err(e);
+        }
+        Ok((id, rx))
+    }
+
+    /// Stops waiting for the response to request `id`.
+    fn forget(&self, id: u64) {
+        if let Some(pending) = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+        {
+            pending.remove(&id);
+        }
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<()> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&self.stdin, &message).await
+    }
+
+    /// Stops the server process.
+    pub fn shutdown(&self) {
+        let _ = self
+            .child
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .start_kill();
+    }
+
+    /// Stops a server that failed to start, adding its last stderr line to
+    /// the error: that line usually says what went wrong.
+    fn with_stderr(&self, error: anyhow::Error) -> anyhow::Error {
+        self.shutdown();
+        let tail = self.stderr.lock().unwrap_or_else(|e| e.into_inner());
+        match tail.back() {
+            Some(line) => error.context(line.clone()),
+            None => error,
+        }
+    }
+}
+
+/// Turns what arrived for a request into its result.
+fn read_response(
+    method: &str,
+    response: Result<Result<Value, String>, oneshot::error::RecvError>,
+) -> Result<Value> {
+    match response {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(e)) => bail!("{}: {}", method, e),
+        Err(_) => bail!("{}: server exited", method),
+    }
+}
+
+async fn write_message(stdin: &tokio::sync::Mutex<ChildStdin>, message: &Value) -> Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    let mut stdin = stdin.lock().await;
+    stdin.write_all(line.as_bytes()).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+/// Routes responses to waiting requests and answers the server's own
+/// requests until stdout closes.
+async fn read_messages(
+    stdout: tokio::process::ChildStdout,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Pending,
+    exited: oneshot::Sender<()>,
+) {
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        match (message.get("id"), message.get("method")) {
+            // A request from the server. Only ping is supported.
+            (Some(id), Some(method)) => {
+                let reply = if method == "ping" {
+                    json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+                } else {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": "Method not found" },
+                    })
+                };
+                let _ = write_message(&stdin, &reply).await;
+            }
+            (Some(id), None) => {
+                let Some(id) = id.as_u64() else {
+                    continue;
+                };
+                let waiter = pending
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .as_mut()
+                    .and_then(|pending| pending.remove(&id));
+                if let Some(waiter) = waiter {
+                    let result = match message.get("error") {
+                        Some(error) => Err(error["message"]
+                            .as_str()
+                            .unwrap_or("unknown error")
+                            .to_string()),
+                        None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                    };
+                    let _ = waiter.send(result);
+                }
+            }
+            // Notifications are not acted on
+            _ => {}
+        }
+    }
+    // Dropping the waiters fails their requests
+    pending.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let _ = exited.send(());
+}
+
+/// An MCP server's tool, offered to the model as `mcp__<server>__<tool>`.
+pub struct McpToolHandle {
+    session: Arc<McpSession>,
+    tool: McpTool,
+    name: String,
+}
+
+impl McpToolHandle {
+    pub fn new(server: &str, session: Arc<McpSession>, tool: McpTool) -> Self {
+        // Tool names may only use letters, digits, `_` and `-`, up to 64
+        let name: String = format!("mcp__{}__{}", server, tool.name)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .take(64)
+            .collect();
+        Self {
+            session,
+            tool,
+            name,
+        }
+    }
+}
+
+impl Tool for McpToolHandle {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        self.tool.description.as_deref().unwrap_or_default()
+    }
+
+    fn input_schema(&self) -> Value {
+        self.tool.input_schema.clone()
+    }
+
+    fn run(&self, input: Value) -> ToolFuture {
+        let session = self.session.clone();
+        let name = self.tool.name.clone();
+        Box::pin(async move { session.call_tool(&name, input).await })
+    }
+}
+
+/// Where a configured server is in its lifecycle.
+pub enum McpStatus {
+    Starting,
+    Running(Arc<McpSession>),
+    Failed(String),
+}
+
+pub struct McpServer {
+    pub name: String,
+    pub config: McpServerConfig,
+    pub status: McpStatus,
+    /// Changes on every restart so events from an earlier process are
+    /// ignored.
+    pub generation: u64,
+}
+
+impl McpServer {
+    pub fn new(name: String, config: McpServerConfig) -> Self {
+        Self {
+            name,
+            config,
+            status: McpStatus::Starting,
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub fn session(&self) -> Option<&Arc<McpSession>> {
+        match &self.status {
+            McpStatus::Running(session) => Some(session),
+            _ => None,
+        }
+    }
+
+    /// Stops the current process, if any, and marks the server as starting
+    /// again.
+    pub fn restart(&mut self) {
+        if let Some(session) = self.session() {
+            session.shutdown();
+        }
+        self.generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        self.status = McpStatus::Starting;
+    }
+}
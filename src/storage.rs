@@ -1,16 +1,81 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 
-use crate::conversation::{Conversation, Message, Role};
+use crate::attachment::Attachment;
+use crate::conversation::{
+    Conversation, Message, Role, ThinkingBlock, ToolCall, ToolResult, Usage,
+};
 
 #[derive(Serialize, Deserialize)]
 struct SavedMessage {
     role: String,
     content: String,
     timestamp: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    interrupted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<SavedUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stop_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    thinking: Vec<SavedThinking>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<SavedToolCall>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<SavedAttachment>,
+}
+
+/// Only the reference is stored; the image stays where it was attached from.
+#[derive(Serialize, Deserialize)]
+struct SavedAttachment {
+    path: PathBuf,
+    media_type: String,
+    size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedToolCall {
+    id: String,
+    name: String,
+    input: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<SavedToolResult>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedToolResult {
+    content: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    is_error: bool,
+}
+
+/// Stored in the API's own block format.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SavedThinking {
+    Thinking { thinking: String, signature: String },
+    RedactedThinking { data: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    cache_creation_input_tokens: u64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    cache_read_input_tokens: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,11 +86,32 @@ struct SavedConversation {
     messages: Vec<SavedMessage>,
 }
 
-pub fn get_storage_dir() -> PathBuf {
-    let data_dir = dirs::data_local_dir()
+#[derive(Serialize, Deserialize)]
+struct SavedSession {
+    conversations: Vec<String>,
+    active: Option<String>,
+}
+
+/// Overrides the data directory, set from the config file.
+static DATA_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Stores conversations and the session file under `dir` instead of the
+/// platform data directory. `None` restores the default.
+pub fn set_storage_dir(dir: Option<PathBuf>) {
+    *DATA_DIR.write().unwrap_or_else(|e| e.into_inner()) = dir;
+}
+
+fn get_data_dir() -> PathBuf {
+    if let Some(dir) = DATA_DIR.read().unwrap_or_else(|e| e.into_inner()).clone() {
+        return dir;
+    }
+    dirs::data_local_dir()
         .unwrap_or_else(|| PathBuf::from("."))
         .join("claude-tui")
-        .join("conversations");
+}
+
+pub fn get_storage_dir() -> PathBuf {
+    let data_dir = get_data_dir().join("conversations");
 
     // Create directory if it doesn't exist
     let _ = fs::create_dir_all(&data_dir);
@@ -48,6 +134,53 @@ pub fn save_conversation(conv: &Conversation) -> Result<PathBuf> {
                 },
                 content: m.content.clone(),
                 timestamp: m.timestamp.to_rfc3339(),
+                interrupted: m.interrupted,
+                model: m.model.clone(),
+                usage: m.usage.map(|u| SavedUsage {
+                    input_tokens: u.input_tokens,
+                    output_tokens: u.output_tokens,
+                    cache_creation_input_tokens: u.cache_creation_input_tokens,
+                    cache_read_input_tokens: u.cache_read_input_tokens,
+                }),
+                stop_reason: m.stop_reason.clone(),
+                thinking: m
+                    .thinking
+                    .iter()
+                    .map(|block| match block {
+                        ThinkingBlock::Thinking {
+                            thinking,
+                            signature,
+                        } => SavedThinking::Thinking {
+                            thinking: thinking.clone(),
+                            signature: signature.clone(),
+                        },
+                        ThinkingBlock::Redacted { data } => {
+                            SavedThinking::RedactedThinking { data: data.clone() }
+                        }
+                    })
+                    .collect(),
+                tool_calls: m
+                    .tool_calls
+                    .iter()
+                    .map(|call| SavedToolCall {
+                        id: call.id.clone(),
+                        name: call.name.clone(),
+                        input: call.input.clone(),
+                        result: call.result.as_ref().map(|r| SavedToolResult {
+                            content: r.content.clone(),
+                            is_error: r.is_error,
+                        }),
+                    })
+                    .collect(),
+                attachments: m
+                    .attachments
+                    .iter()
+                    .map(|a| SavedAttachment {
+                        path: a.path.clone(),
+                        media_type: a.media_type.clone(),
+                        size: a.size,
+                    })
+                    .collect(),
             })
             .collect(),
     };
@@ -59,7 +192,92 @@ pub fn save_conversation(conv: &Conversation) -> Result<PathBuf> {
     Ok(path)
 }
 
-pub fn list_saved_conversations() -> Result<Vec<(PathBuf, String)>> {
+pub fn load_conversation(path: &Path) -> Result<Conversation> {
+    let content = fs::read_to_string(path)?;
+    let saved: SavedConversation = serde_json::from_str(&content)?;
+
+    let messages = saved
+        .messages
+        .into_iter()
+        .map(|m| {
+            let role = match m.role.as_str() {
+                "user" => Role::User,
+                "assistant" => Role::Assistant,
+                other => return Err(anyhow!("Unknown role: {}", other)),
+            };
+            let timestamp = DateTime::parse_from_rfc3339(&m.timestamp)?.with_timezone(&Local);
+            Ok(Message {
+                role,
+                content: m.content,
+                timestamp,
+                interrupted: m.interrupted,
+                model: m.model,
+                usage: m.usage.map(|u| Usage {
+                    input_tokens: u.input_tokens,
+                    output_tokens: u.output_tokens,
+                    cache_creation_input_tokens: u.cache_creation_input_tokens,
+                    cache_read_input_tokens: u.cache_read_input_tokens,
+                }),
+                stop_reason: m.stop_reason,
+                thinking: m
+                    .thinking
+                    .into_iter()
+                    .map(|block| match block {
+                        SavedThinking::Thinking {
+                            thinking,
+                            signature,
+                        } => ThinkingBlock::Thinking {
+                            thinking,
+                            signature,
+                        },
+                        SavedThinking::RedactedThinking { data } => {
+                            ThinkingBlock::Redacted { data }
+                        }
+                    })
+                    .collect(),
+                tool_calls: m
+                    .tool_calls
+                    .into_iter()
+                    .map(|call| ToolCall {
+                        id: call.id,
+                        name: call.name,
+                        input: call.input,
+                        result: call.result.map(|r| ToolResult {
+                            content: r.content,
+                            is_error: r.is_error,
+                        }),
+                    })
+                    .collect(),
+                attachments: m
+                    .attachments
+                    .into_iter()
+                    .map(|a| Attachment::restore(a.path, a.media_type, a.size))
+                    .collect(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut conv = Conversation::new();
+    conv.id = saved.id.parse()?;
+    conv.saved = true;
+    conv.title = saved.title;
+    conv.system_prompt = saved.system_prompt;
+    conv.messages = messages;
+    Ok(conv)
+}
+
+/// Metadata about a saved conversation, used by the conversation browser.
+pub struct ConversationSummary {
+    pub path: PathBuf,
+    pub id: String,
+    pub title: String,
+    pub message_count: usize,
+    pub updated: DateTime<Local>,
+    /// The first user message and the reply to it, if any.
+    pub preview: Vec<(Role, String)>,
+}
+
+pub fn list_saved_conversations() -> Result<Vec<ConversationSummary>> {
     let dir = get_storage_dir();
     let mut results = Vec::new();
 
@@ -69,13 +287,355 @@ pub fn list_saved_conversations() -> Result<Vec<(PathBuf, String)>> {
             if path.extension().map(|e| e == "json").unwrap_or(false) {
                 if let Ok(content) = fs::read_to_string(&path) {
                     if let Ok(saved) = serde_json::from_str::<SavedConversation>(&content) {
-                        let title = saved.title.unwrap_or_else(|| "Untitled".to_string());
-                        results.push((path, title));
+                        let updated = entry
+                            .metadata()
+                            .and_then(|m| m.modified())
+                            .map(DateTime::<Local>::from)
+                            .unwrap_or_else(|_| Local::now());
+                        results.push(summarize(path, saved, updated));
                     }
                 }
             }
         }
     }
 
+    // Most recently saved first
+    results.sort_by_key(|s| std::cmp::Reverse(s.updated));
+
     Ok(results)
 }
+
+fn summarize(
+    path: PathBuf,
+    saved: SavedConversation,
+    updated: DateTime<Local>,
+) -> ConversationSummary {
+    let preview = saved
+        .messages
+        .iter()
+        .skip_while(|m| m.role != "user")
+        .take(2)
+        .map(|m| {
+            let role = if m.role == "user" {
+                Role::User
+            } else {
+                Role::Assistant
+            };
+            (role, m.content.chars().take(500).collect())
+        })
+        .collect();
+
+    ConversationSummary {
+        path,
+        id: saved.id,
+        title: saved.title.unwrap_or_else(|| "Untitled".to_string()),
+        message_count: saved.messages.len(),
+        updated,
+        preview,
+    }
+}
+
+pub fn rename_conversation(path: &Path, title: &str) -> Result<()> {
+    let content = fs::read_to_string(path)?;
+    let mut saved: SavedConversation = serde_json::from_str(&content)?;
+    saved.title = Some(title.to_string());
+    fs::write(path, serde_json::to_string_pretty(&saved)?)?;
+    Ok(())
+}
+
+pub fn delete_conversation(path: &Path) -> Result<()> {
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+fn session_path() -> PathBuf {
+    get_data_dir().join("session.json")
+}
+
+/// Records which conversations are open and which one is active, so the
+/// workspace can be restored on the next start.
+pub fn save_session(conversations: &[&Conversation], active: Option<&Conversation>) -> Result<()> {
+    let session = SavedSession {
+        conversations: conversations.iter().map(|c| c.id.to_string()).collect(),
+        active: active.map(|c| c.id.to_string()),
+    };
+
+    let path = session_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Write to a temp file first so a crash mid-write can't corrupt the session
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&session)?)?;
+    fs::rename(&tmp, &path)?;
+
+    Ok(())
+}
+
+/// Loads the conversations recorded in the session file, returning them along
+/// with the index of the active one. Conversations whose files have since
+/// been deleted are skipped.
+pub fn load_session() -> Result<(Vec<Conversation>, usize)> {
+    let content = fs::read_to_string(session_path())?;
+    let session: SavedSession = serde_json::from_str(&content)?;
+
+    let dir = get_storage_dir();
+    let conversations: Vec<Conversation> = session
+        .conversations
+        .iter()
+        .filter_map(|id| load_conversation(&dir.join(format!("{}.json", id))).ok())
+        .collect();
+
+    let active = session
+        .active
+        .and_then(|id| conversations.iter().position(|c| c.id.to_string() == id))
+        .unwrap_or(0);
+
+    Ok((conversations, active))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use serde_json::json;
+
+    /// The storage directory is global, so tests that use it take turns.
+    static STORAGE: Mutex<()> = Mutex::new(());
+
+    /// Runs `test` with storage in a fresh directory, removed afterwards.
+    fn in_temp_storage(test: impl FnOnce(&Path)) {
+        let _guard = STORAGE.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("claude-tui-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        set_storage_dir(Some(dir.clone()));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| test(&dir)));
+        set_storage_dir(None);
+        let _ = fs::remove_dir_all(&dir);
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    fn conversation(title: &str, content: &str) -> Conversation {
+        let mut conv = Conversation::new();
+        conv.title = Some(title.to_string());
+        conv.messages
+            .push(Message::new(Role::User, content.to_string()));
+        conv
+    }
+
+    #[test]
+    fn round_trips_every_message_field() {
+        in_temp_storage(|dir| {
+            let image = dir.join("pixel.png");
+            fs::write(&image, b"\x89PNG\r\n\x1a\nrest").unwrap();
+
+            let mut question = Message::new(Role::User, "What is this?".to_string());
+            question.attachments = vec![
+                Attachment::load(&image).unwrap(),
+                Attachment::restore(dir.join("gone.jpg"), "image/jpeg".to_string(), 7),
+            ];
+            let mut reply = Message::new(Role::Assistant, "A pixel.".to_string());
+            reply.interrupted = true;
+            reply.model = Some("claude-test".to_string());
+            reply.usage = Some(Usage {
+                input_tokens: 10,
+                output_tokens: 20,
+                cache_creation_input_tokens: 30,
+                cache_read_input_tokens: 40,
+            });
+            reply.stop_reason = Some("tool_use".to_string());
+            reply.thinking = vec![
+                ThinkingBlock::Thinking {
+                    thinking: "Looks small".to_string(),
+                    signature: "sig".to_string(),
+                },
+                ThinkingBlock::Redacted {
+                    data: "secret".to_string(),
+                },
+            ];
+            reply.tool_calls = vec![
+                ToolCall {
+                    id: "a".to_string(),
+                    name: "read_file".to_string(),
+                    input: json!({ "path": "pixel.png" }),
+                    result: Some(ToolResult {
+                        content: "binary".to_string(),
+                        is_error: true,
+                    }),
+                },
+                ToolCall {
+                    id: "b".to_string(),
+                    name: "list_dir".to_string(),
+                    input: json!({}),
+                    result: None,
+                },
+            ];
+            let mut conv = Conversation::new();
+            conv.title = Some("Pixels".to_string());
+            conv.system_prompt = Some("Be brief.".to_string());
+            conv.messages = vec![question, reply];
+
+            let path = save_conversation(&conv).unwrap();
+            assert_eq!(
+                path,
+                dir.join("conversations").join(format!("{}.json", conv.id))
+            );
+            let loaded = load_conversation(&path).unwrap();
+
+            assert_eq!(loaded.id, conv.id);
+            assert!(loaded.saved);
+            assert_eq!(loaded.title.as_deref(), Some("Pixels"));
+            assert_eq!(loaded.system_prompt.as_deref(), Some("Be brief."));
+            assert_eq!(loaded.messages.len(), 2);
+
+            let question = &loaded.messages[0];
+            assert!(question.role == Role::User);
+            assert_eq!(question.content, "What is this?");
+            assert_eq!(question.timestamp, conv.messages[0].timestamp);
+            assert!(!question.interrupted);
+            let [pixel, gone] = question.attachments.as_slice() else {
+                panic!("expected two attachments");
+            };
+            assert_eq!(pixel.path, image);
+            assert_eq!(pixel.media_type, "image/png");
+            assert_eq!(pixel.size, 12);
+            assert_eq!(pixel.data, conv.messages[0].attachments[0].data);
+            assert_eq!(gone.path, dir.join("gone.jpg"));
+            assert_eq!(gone.media_type, "image/jpeg");
+            assert_eq!(gone.size, 7);
+            assert!(gone.data.is_none());
+
+            let reply = &loaded.messages[1];
+            assert!(reply.role == Role::Assistant);
+            assert_eq!(reply.content, "A pixel.");
+            assert!(reply.interrupted);
+            assert_eq!(reply.model.as_deref(), Some("claude-test"));
+            assert!(reply.usage == conv.messages[1].usage);
+            assert_eq!(reply.stop_reason.as_deref(), Some("tool_use"));
+            assert!(matches!(
+                reply.thinking.as_slice(),
+                [
+                    ThinkingBlock::Thinking { thinking, signature },
+                    ThinkingBlock::Redacted { data },
+                ] if thinking == "Looks small" && signature == "sig" && data == "secret"
+            ));
+            let [read, list] = reply.tool_calls.as_slice() else {
+                panic!("expected two tool calls");
+            };
+            assert_eq!((read.id.as_str(), read.name.as_str()), ("a", "read_file"));
+            assert_eq!(read.input, json!({ "path": "pixel.png" }));
+            assert!(matches!(&read.result, Some(r) if r.content == "binary" && r.is_error));
+            assert_eq!((list.id.as_str(), list.name.as_str()), ("b", "list_dir"));
+            assert!(list.result.is_none());
+        });
+    }
+
+    #[test]
+    fn leaves_default_fields_out_of_the_file() {
+        in_temp_storage(|_| {
+            let path = save_conversation(&conversation("Short", "Hi")).unwrap();
+            let saved: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+            let message = saved["messages"][0].as_object().unwrap();
+            let mut keys: Vec<&str> = message.keys().map(String::as_str).collect();
+            keys.sort_unstable();
+            assert_eq!(keys, ["content", "role", "timestamp"]);
+        });
+    }
+
+    #[test]
+    fn refuses_files_with_unknown_roles() {
+        in_temp_storage(|dir| {
+            let path = dir.join("bad.json");
+            let saved = json!({
+                "id": uuid::Uuid::new_v4().to_string(),
+                "title": null,
+                "system_prompt": null,
+                "messages": [{ "role": "system", "content": "x", "timestamp": Local::now().to_rfc3339() }],
+            });
+            fs::write(&path, saved.to_string()).unwrap();
+            let error = load_conversation(&path).err().unwrap();
+            assert_eq!(error.to_string(), "Unknown role: system");
+        });
+    }
+
+    #[test]
+    fn lists_renames_and_deletes_conversations() {
+        in_temp_storage(|_| {
+            let mut first = conversation("First", "One");
+            first.title = None;
+            first
+                .messages
+                .push(Message::new(Role::Assistant, "Reply".to_string()));
+            let first_path = save_conversation(&first).unwrap();
+            let second_path = save_conversation(&conversation("Second", "Two")).unwrap();
+
+            let mut listed = list_saved_conversations().unwrap();
+            listed.sort_by_key(|s| s.message_count);
+            assert_eq!(listed.len(), 2);
+            assert_eq!(listed[0].title, "Second");
+            assert_eq!(listed[1].title, "Untitled");
+            assert_eq!(listed[1].id, first.id.to_string());
+            assert_eq!(listed[1].message_count, 2);
+            let preview: Vec<&str> = listed[1]
+                .preview
+                .iter()
+                .map(|(_, text)| text.as_str())
+                .collect();
+            assert_eq!(preview, ["One", "Reply"]);
+
+            rename_conversation(&first_path, "Renamed").unwrap();
+            assert_eq!(
+                load_conversation(&first_path).unwrap().title.as_deref(),
+                Some("Renamed")
+            );
+            assert_eq!(load_conversation(&first_path).unwrap().messages.len(), 2);
+
+            delete_conversation(&second_path).unwrap();
+            assert!(!second_path.exists());
+            let listed = list_saved_conversations().unwrap();
+            assert_eq!(listed.len(), 1);
+            assert_eq!(listed[0].title, "Renamed");
+        });
+    }
+
+    #[test]
+    fn restores_the_session() {
+        in_temp_storage(|dir| {
+            let first = conversation("First", "One");
+            let second = conversation("Second", "Two");
+            save_conversation(&first).unwrap();
+            save_conversation(&second).unwrap();
+
+            save_session(&[&first, &second], Some(&second)).unwrap();
+            // Written through a temp file that is renamed into place
+            assert!(dir.join("session.json").exists());
+            assert!(!dir.join("session.json.tmp").exists());
+
+            let (conversations, active) = load_session().unwrap();
+            let ids: Vec<_> = conversations.iter().map(|c| c.id).collect();
+            assert_eq!(ids, [first.id, second.id]);
+            assert_eq!(active, 1);
+        });
+    }
+
+    #[test]
+    fn skips_deleted_conversations_in_the_session() {
+        in_temp_storage(|_| {
+            let kept = conversation("Kept", "One");
+            let deleted = conversation("Deleted", "Two");
+            save_conversation(&kept).unwrap();
+            let path = save_conversation(&deleted).unwrap();
+            save_session(&[&kept, &deleted], Some(&deleted)).unwrap();
+            delete_conversation(&path).unwrap();
+
+            let (conversations, active) = load_session().unwrap();
+            assert_eq!(conversations.len(), 1);
+            assert_eq!(conversations[0].id, kept.id);
+            assert_eq!(active, 0);
+        });
+    }
+}
@@ -0,0 +1,282 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Number of terminal columns `text` occupies.
+pub fn display_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// Byte index of the grapheme boundary before `index`.
+pub fn prev_grapheme_boundary(text: &str, index: usize) -> usize {
+    text[..index]
+        .grapheme_indices(true)
+        .next_back()
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+/// Byte index of the grapheme boundary after `index`.
+pub fn next_grapheme_boundary(text: &str, index: usize) -> usize {
+    text[index..]
+        .graphemes(true)
+        .next()
+        .map(|g| index + g.len())
+        .unwrap_or(text.len())
+}
+
+/// Takes graphemes from the front of `text` until adding another would
+/// exceed `width` columns. Returns the prefix and the rest. At least one
+/// grapheme is always taken so callers make progress on very narrow widths.
+pub fn split_at_width(text: &str, width: usize) -> (&str, &str) {
+    let mut used = 0;
+    let mut end = 0;
+    for (i, g) in text.grapheme_indices(true) {
+        let w = display_width(g);
+        if used + w > width && end > 0 {
+            break;
+        }
+        used += w;
+        end = i + g.len();
+    }
+    text.split_at(end)
+}
+
+/// Truncates `text` to at most `width` columns on a grapheme boundary.
+pub fn truncate_to_width(text: &str, width: usize) -> &str {
+    if display_width(text) <= width {
+        return text;
+    }
+    let (head, _) = split_at_width(text, width);
+    if display_width(head) > width {
+        ""
+    } else {
+        head
+    }
+}
+
+/// Word-wraps `text` to `max_width` columns. Words wider than a line are
+/// split on grapheme boundaries.
+pub fn wrap_text(text: &str, max_width: usize) -> Vec<String> {
+    let max_width = max_width.max(1);
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        if line.is_empty() {
+            lines.push(String::new());
+            continue;
+        }
+
+        let mut current_line = String::new();
+        let mut current_width = 0;
+        for word in line.split_whitespace() {
+            let word_width = display_width(word);
+            if !current_line.is_empty() {
+                if current_width + 1 + word_width <= max_width {
+                    current_line.push(' ');
+                    current_line.push_str(word);
+                    current_width += 1 + word_width;
+                    continue;
+                }
+                lines.push(std::mem::take(&mut current_line));
+            }
+
+            // Word is too long, split it on grapheme boundaries
+            let mut remaining = word;
+            while display_width(remaining) > max_width {
+                let (head, tail) = split_at_width(remaining, max_width);
+                lines.push(head.to_string());
+                remaining = tail;
+            }
+            current_line = remaining.to_string();
+            current_width = display_width(remaining);
+        }
+        if !current_line.is_empty() {
+            lines.push(current_line);
+        }
+    }
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    lines
+}
+
+/// Byte index of the start of the line containing `index`.
+pub fn line_start(text: &str, index: usize) -> usize {
+    text[..index].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+/// Byte index of the end of the line containing `index` (before its `\n`).
+pub fn line_end(text: &str, index: usize) -> usize {
+    text[index..]
+        .find('\n')
+        .map(|i| index + i)
+        .unwrap_or(text.len())
+}
+
+/// Byte offset into `line` of the last grapheme boundary at or before
+/// display column `column`.
+fn index_at_column(line: &str, column: usize) -> usize {
+    let mut used = 0;
+    for (i, g) in line.grapheme_indices(true) {
+        let w = display_width(g);
+        if used + w > column {
+            return i;
+        }
+        used += w;
+    }
+    line.len()
+}
+
+/// Moves `index` to the same display column on the previous line, or to the
+/// start of the text when already on the first line.
+pub fn line_up(text: &str, index: usize) -> usize {
+    let start = line_start(text, index);
+    if start == 0 {
+        return 0;
+    }
+    let column = display_width(&text[start..index]);
+    let prev_start = line_start(text, start - 1);
+    prev_start + index_at_column(&text[prev_start..start - 1], column)
+}
+
+/// Moves `index` to the same display column on the next line, or to the end
+/// of the text when already on the last line.
+pub fn line_down(text: &str, index: usize) -> usize {
+    let end = line_end(text, index);
+    if end == text.len() {
+        return text.len();
+    }
+    let column = display_width(&text[line_start(text, index)..index]);
+    let next_start = end + 1;
+    let next_end = line_end(text, next_start);
+    next_start + index_at_column(&text[next_start..next_end], column)
+}
+
+/// Byte index of the start of the word before `index`, skipping any
+/// whitespace in between.
+pub fn word_start_before(text: &str, index: usize) -> usize {
+    let mut graphemes = text[..index].grapheme_indices(true).rev().peekable();
+    let mut position = index;
+    while let Some((i, _)) = graphemes.next_if(|(_, g)| g.trim().is_empty()) {
+        position = i;
+    }
+    while let Some((i, _)) = graphemes.next_if(|(_, g)| !g.trim().is_empty()) {
+        position = i;
+    }
+    position
+}
+
+/// Byte index of the end of the word after `index`, skipping any whitespace
+/// in between.
+pub fn word_end_after(text: &str, index: usize) -> usize {
+    let mut graphemes = text[index..].grapheme_indices(true).peekable();
+    let mut position = index;
+    while let Some((i, g)) = graphemes.next_if(|(_, g)| g.trim().is_empty()) {
+        position = index + i + g.len();
+    }
+    while let Some((i, g)) = graphemes.next_if(|(_, g)| !g.trim().is_empty()) {
+        position = index + i + g.len();
+    }
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY: &str = "👨\u{200d}👩\u{200d}👧";
+
+    #[test]
+    fn split_at_width_keeps_wide_characters_whole() {
+        assert_eq!(split_at_width("日本語", 4), ("日本", "語"));
+        assert_eq!(split_at_width("日本語", 5), ("日本", "語"));
+        assert_eq!(split_at_width("abc", 10), ("abc", ""));
+    }
+
+    #[test]
+    fn split_at_width_always_takes_one_grapheme() {
+        assert_eq!(split_at_width("日本", 1), ("日", "本"));
+        assert_eq!(split_at_width("e\u{301}x", 0), ("e\u{301}", "x"));
+        assert_eq!(split_at_width("", 3), ("", ""));
+    }
+
+    #[test]
+    fn split_at_width_does_not_break_clusters() {
+        let text = format!("{}{}", FAMILY, FAMILY);
+        assert_eq!(split_at_width(&text, 3), (FAMILY, FAMILY));
+        assert_eq!(split_at_width("ae\u{301}b", 2), ("ae\u{301}", "b"));
+    }
+
+    #[test]
+    fn grapheme_boundaries_step_over_clusters() {
+        let text = format!("a{}e\u{301}", FAMILY);
+        let after_family = 1 + FAMILY.len();
+        assert_eq!(next_grapheme_boundary(&text, 0), 1);
+        assert_eq!(next_grapheme_boundary(&text, 1), after_family);
+        assert_eq!(next_grapheme_boundary(&text, after_family), text.len());
+        assert_eq!(next_grapheme_boundary(&text, text.len()), text.len());
+
+        assert_eq!(prev_grapheme_boundary(&text, text.len()), after_family);
+        assert_eq!(prev_grapheme_boundary(&text, after_family), 1);
+        assert_eq!(prev_grapheme_boundary(&text, 1), 0);
+        assert_eq!(prev_grapheme_boundary(&text, 0), 0);
+    }
+
+    #[test]
+    fn truncate_to_width_never_exceeds_width() {
+        assert_eq!(truncate_to_width("hello", 10), "hello");
+        assert_eq!(truncate_to_width("hello", 3), "hel");
+        assert_eq!(truncate_to_width("日本語", 3), "日");
+        assert_eq!(truncate_to_width("日本語", 1), "");
+        assert_eq!(truncate_to_width(FAMILY, 1), "");
+        assert_eq!(truncate_to_width("e\u{301}e\u{301}", 1), "e\u{301}");
+    }
+
+    #[test]
+    fn line_up_and_down_keep_the_display_column() {
+        let text = "日本語\nabcdef";
+        let second = "日本語\n".len();
+        // Column 3 falls inside 本, so the cursor lands before it
+        assert_eq!(line_up(text, second + 3), "日".len());
+        assert_eq!(line_up(text, second + 4), "日本".len());
+        assert_eq!(line_up(text, text.len()), "日本語".len());
+        assert_eq!(line_down(text, "日".len()), second + 2);
+        assert_eq!(line_down(text, "日本語".len()), second + 6);
+    }
+
+    #[test]
+    fn line_up_and_down_stop_at_the_ends() {
+        let text = "abc\ndef";
+        assert_eq!(line_up(text, 2), 0);
+        assert_eq!(line_down(text, 5), text.len());
+        assert_eq!(line_up("", 0), 0);
+        assert_eq!(line_down("", 0), 0);
+    }
+
+    #[test]
+    fn wrap_text_breaks_between_words() {
+        assert_eq!(wrap_text("one two three", 7), ["one two", "three"]);
+        assert_eq!(wrap_text("a\n\nb", 10), ["a", "", "b"]);
+        assert_eq!(wrap_text("", 10), [""]);
+    }
+
+    #[test]
+    fn wrap_text_splits_long_words_by_width() {
+        assert_eq!(wrap_text("日本語です", 4), ["日本", "語で", "す"]);
+        assert_eq!(wrap_text("日本 語", 3), ["日", "本", "語"]);
+        for line in wrap_text("漢字かな交じり文 and some ascii", 5) {
+            assert!(display_width(&line) <= 5, "{:?} is too wide", line);
+        }
+    }
+
+    #[test]
+    fn wrap_text_keeps_clusters_together() {
+        let family = FAMILY.repeat(3);
+        assert_eq!(wrap_text(&family, 3), [FAMILY; 3]);
+        assert_eq!(
+            wrap_text("e\u{301}e\u{301}e\u{301}", 2),
+            ["e\u{301}e\u{301}", "e\u{301}"]
+        );
+        // A single column still fits one wide character per line
+        assert_eq!(wrap_text("日本", 1), ["日", "本"]);
+    }
+}
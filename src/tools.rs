@@ -0,0 +1,92 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::api::ToolDefinition;
+use crate::conversation::{ToolCall, ToolResult};
+
+/// Output beyond this many bytes is cut off before it reaches the model.
+const MAX_OUTPUT_BYTES: usize = 100_000;
+
+/// The output of a tool run: text handed back to the model.
+pub type ToolFuture = Pin<Box<dyn Future<Output = Result<String>> + Send>>;
+
+/// Something the model can ask to run. Errors are reported back to the
+/// model as failed results rather than ending the conversation.
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    /// JSON Schema of the input object.
+    fn input_schema(&self) -> Value;
+    fn run(&self, input: Value) -> ToolFuture;
+}
+
+/// The tools offered to the model, in the order they were registered.
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tool, replacing any registered under the same name.
+    pub fn register(&mut self, tool: Arc<dyn Tool>) {
+        self.tools.retain(|t| t.name() != tool.name());
+        self.tools.push(tool);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.tools.iter().find(|t| t.name() == name).cloned()
+    }
+
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .iter()
+            .map(|tool| ToolDefinition {
+                name: tool.name().to_string(),
+                description: tool.description().to_string(),
+                input_schema: tool.input_schema(),
+            })
+            .collect()
+    }
+
+    /// Runs a call, turning an unknown tool or a failure into an error
+    /// result.
+    pub async fn run(&self, call: &ToolCall) -> ToolResult {
+        let Some(tool) = self.get(&call.name) else {
+            return ToolResult {
+                content: format!("Unknown tool: {}", call.name),
+                is_error: true,
+            };
+        };
+        match tool.run(call.input.clone()).await {
+            Ok(content) => ToolResult {
+                content,
+                is_error: false,
+            },
+            Err(e) => ToolResult {
+                content: format!("{:#}", e),
+                is_error: true,
+            },
+        }
+    }
+}
+
+/// Cuts `output` down to what a tool may hand back, marking the cut.
+pub fn truncate_output(mut output: String) -> String {
+    if output.len() > MAX_OUTPUT_BYTES {
+        let mut end = MAX_OUTPUT_BYTES;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str("\n[output truncated]");
+    }
+    output
+}
@@ -1,101 +1,231 @@
+use std::time::Instant;
+
+use chrono::{DateTime, Local};
 use ratatui::{
     layout::{Alignment, Constraint, Flex, Layout, Rect},
-    style::{Color, Modifier, Style, Stylize},
+    style::{Modifier, Style, Stylize},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, Paragraph, Tabs, Wrap},
+    widgets::{
+        Block, Borders, Clear, Padding, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState,
+        Tabs, Wrap,
+    },
     Frame,
 };
 
 use crate::app::{App, Mode};
-use crate::conversation::Role;
+use crate::attachment::{self, Attachment};
+use crate::browser::{BrowseInput, Browser};
+use crate::config::{Config, Theme};
+use crate::conversation::{Message, Role, ThinkingBlock, ToolCall};
+use crate::markdown;
+use crate::mcp::{McpServer, McpStatus};
+use crate::text;
+
+/// Share of the context window at which the token count turns into a warning.
+const CONTEXT_WARNING: f64 = 0.8;
 
-pub fn render(app: &App, frame: &mut Frame) {
+/// Lines of a tool result shown in the conversation.
+const TOOL_PREVIEW_LINES: usize = 4;
+
+pub fn render(app: &mut App, frame: &mut Frame) {
     let area = frame.area();
+    let theme = &app.config.theme;
 
     // Outer border
     let outer_block = Block::default()
         .title(" claude-tui ")
         .borders(Borders::ALL)
         .border_type(ratatui::widgets::BorderType::Rounded)
-        .style(Style::default().fg(Color::Cyan));
+        .style(Style::default().fg(theme.accent));
 
     let inner_area = outer_block.inner(area);
     frame.render_widget(outer_block, area);
 
+    // Lay out the input first: the box grows with its content
+    let input_width = inner_area.width.saturating_sub(4) as usize;
+    let (input_rows, (cursor_row, cursor_col)) =
+        layout_input(&app.input, app.cursor_position, input_width);
+    let input_height = input_rows.len().clamp(1, app.max_input_height.max(1));
+
     // Split inner area: tabs, messages, input, status
     let chunks = Layout::vertical([
-        Constraint::Length(1), // Tab bar
-        Constraint::Min(1),    // Messages area
-        Constraint::Length(3), // Input area
-        Constraint::Length(1), // Status bar
+        Constraint::Length(1),                       // Tab bar
+        Constraint::Min(1),                          // Messages area
+        Constraint::Length(input_height as u16 + 2), // Input area
+        Constraint::Length(1),                       // Status bar
     ])
     .split(inner_area);
 
-    // Render tabs
-    render_tabs(app, frame, chunks[0]);
+    // Keep the cursor row inside the visible part of the input
+    let mut input_scroll = app.input_scroll.min(input_rows.len() - input_height);
+    if cursor_row < input_scroll {
+        input_scroll = cursor_row;
+    } else if cursor_row >= input_scroll + input_height {
+        input_scroll = cursor_row + 1 - input_height;
+    }
 
-    // Render messages
-    render_messages(app, frame, chunks[1]);
+    let input_lines: Vec<Line> = input_rows
+        .iter()
+        .enumerate()
+        .skip(input_scroll)
+        .take(input_height)
+        .map(|(i, row)| Line::from(format!("{}{}", if i == 0 { "> " } else { "  " }, row)))
+        .collect();
 
     // Input area
     let input_border_color = match app.mode {
-        Mode::Insert => Color::Blue,
-        Mode::Normal | Mode::Help => Color::Gray,
+        Mode::Insert => theme.user,
+        Mode::Normal | Mode::Help | Mode::Browse | Mode::Approve | Mode::Mcp => theme.inactive,
     };
 
-    let input_block = Block::default()
+    let mut input_block = Block::default()
         .borders(Borders::ALL)
         .border_type(ratatui::widgets::BorderType::Rounded)
         .border_style(Style::default().fg(input_border_color));
+    if let Some(title) = token_count_title(app) {
+        input_block = input_block.title(title.right_aligned());
+    }
+    if !app.attachments.is_empty() {
+        let names: Vec<String> = app.attachments.iter().map(Attachment::file_name).collect();
+        input_block = input_block.title(Span::styled(
+            format!(" 📎 {} ", names.join(", ")),
+            Style::default().fg(theme.prompt),
+        ));
+    }
 
-    let input_text = format!("> {}", app.input);
-    let input_paragraph = Paragraph::new(input_text).block(input_block);
+    let input_paragraph = Paragraph::new(input_lines).block(input_block);
     frame.render_widget(input_paragraph, chunks[2]);
 
     // Show cursor in insert mode
     if app.mode == Mode::Insert {
-        let cursor_x = chunks[2].x + 3 + app.cursor_position as u16;
-        let cursor_y = chunks[2].y + 1;
+        let cursor_x = chunks[2].x + 3 + cursor_col as u16;
+        let cursor_y = chunks[2].y + 1 + (cursor_row - input_scroll) as u16;
         frame.set_cursor_position((cursor_x, cursor_y));
     }
+    app.input_scroll = input_scroll;
+
+    // Render tabs
+    render_tabs(app, frame, chunks[0]);
+
+    // Render messages
+    render_messages(app, frame, chunks[1]);
 
     // Status bar
     render_status_bar(app, frame, chunks[3]);
 
     // Help overlay
     if app.mode == Mode::Help {
-        render_help_overlay(frame, area);
+        render_help_overlay(&app.config, frame, area);
+    }
+
+    // Conversation browser overlay
+    if let (Mode::Browse, Some(browser)) = (app.mode, app.browser.as_ref()) {
+        render_browser_overlay(browser, &app.config.theme, frame, area);
+    }
+
+    if app.mode == Mode::Mcp {
+        render_mcp_overlay(app, frame, area);
+    }
+
+    if app.mode == Mode::Approve {
+        render_approval_overlay(app, frame, area);
     }
 }
 
 fn render_status_bar(app: &App, frame: &mut Frame, area: Rect) {
     let msg_count = app.current_conversation().messages.len();
-    let loading_indicator = if app.is_loading { " (thinking...)" } else { "" };
+    let loading_indicator = match app.retries.get(&app.current_conversation().id) {
+        Some((at, retry)) => format!(
+            " (retrying in {}s, attempt {}/{}: {})",
+            at.saturating_duration_since(Instant::now())
+                .as_secs_f32()
+                .ceil(),
+            retry.attempt,
+            retry.max_retries,
+            retry.reason
+        ),
+        None if app.is_loading() => match running_tool(app) {
+            Some(_)
+                if app
+                    .approvals
+                    .iter()
+                    .any(|a| a.conversation_id == app.current_conversation().id) =>
+            {
+                " (waiting for approval)".to_string()
+            }
+            Some(name) => format!(" (running {}...)", name),
+            None => " (thinking...)".to_string(),
+        },
+        None => String::new(),
+    };
+    let raw_indicator = if app.show_raw_markdown { " [raw]" } else { "" };
+    let think_indicator = match app.thinking_budget {
+        Some(budget) => format!(" [think {}]", format_tokens(budget as u64)),
+        None => String::new(),
+    };
+    let system_indicator = if app.current_conversation().system_prompt.is_some() {
+        " | ⚙ system"
+    } else {
+        ""
+    };
+    let mcp_indicator = match app.mcp_servers.len() {
+        0 => String::new(),
+        total => {
+            let running = app
+                .mcp_servers
+                .iter()
+                .filter(|s| s.session().is_some())
+                .count();
+            format!(" | MCP {}/{}", running, total)
+        }
+    };
+    let usage_text = usage_summary(app);
     let api_warning = if !app.has_api_key() {
         " ⚠ ANTHROPIC_API_KEY not set"
     } else {
         ""
     };
 
+    let keys = &app.config.keys;
     let status_text = match app.mode {
         Mode::Normal => format!(
-            "NORMAL | {} msgs | i insert  j/k scroll  ^n new  ^w close  ^s save  ? help  q quit{}{}",
-            msg_count, loading_indicator, api_warning
+            "NORMAL | {} msgs{}{}{}{}{} | {} insert  {}/{} scroll  {} new  {} close  {} save  {} cancel  {} help  {} quit{}{}",
+            msg_count,
+            usage_text,
+            system_indicator,
+            mcp_indicator,
+            raw_indicator,
+            think_indicator,
+            keys.insert,
+            keys.scroll_down,
+            keys.scroll_up,
+            keys.new_conversation,
+            keys.close_conversation,
+            keys.save,
+            keys.cancel,
+            keys.help,
+            keys.quit,
+            loading_indicator,
+            api_warning
         ),
         Mode::Insert => format!(
-            "INSERT | Esc → normal  Enter → send{}{}",
+            "INSERT | Esc → normal  Enter → send  Alt+Enter → newline{}{}",
             loading_indicator, api_warning
         ),
         Mode::Help => "HELP | Press any key to close".to_string(),
+        Mode::Browse => "BROWSE | j/k select  Enter open  / filter  s sort  r rename  d delete  Esc close".to_string(),
+        Mode::Mcp => "MCP | j/k select  r restart  Esc close".to_string(),
+        Mode::Approve => "APPROVE | y allow once  Y always for this conversation  n/Esc deny".to_string(),
     };
 
     // Show status message, error, or default
+    let theme = &app.config.theme;
     let (display_text, status_color) = if let Some(ref error) = app.error_message {
-        (format!("Error: {}", error), Color::Red)
+        (format!("Error: {}", error), theme.error)
     } else if let Some(ref status) = app.status_message {
-        (status.clone(), Color::Green)
+        (status.clone(), theme.status)
     } else {
-        (status_text, Color::DarkGray)
+        (status_text, theme.muted)
     };
 
     let status = Paragraph::new(Line::from(vec![Span::styled(
@@ -105,40 +235,166 @@ fn render_status_bar(app: &App, frame: &mut Frame, area: Rect) {
     frame.render_widget(status, area);
 }
 
-fn render_help_overlay(frame: &mut Frame, area: Rect) {
+/// Projected input tokens of the next request, e.g. ` ~1.2k tokens `. The
+/// `~` marks a local estimate. Turns into a warning near the context window.
+fn token_count_title(app: &App) -> Option<Line<'static>> {
+    let count = app.token_count.as_ref()?;
+    let fraction = app.context_fraction()?;
+    let theme = &app.config.theme;
+
+    let mut text = format!(
+        " {}{} tokens",
+        if count.exact { "" } else { "~" },
+        format_tokens(count.tokens)
+    );
+    let style = if fraction >= CONTEXT_WARNING {
+        text.push_str(&format!(" · {:.0}% of context", fraction * 100.0));
+        Style::default().fg(theme.error)
+    } else {
+        Style::default().fg(theme.muted)
+    };
+    text.push(' ');
+    Some(Line::from(Span::styled(text, style)))
+}
+
+/// Token counts and cost of the active conversation, plus the session cost.
+fn usage_summary(app: &App) -> String {
+    let conv = app.current_conversation();
+    let usage = conv.total_usage();
+    if usage.total_input() == 0 && usage.output_tokens == 0 {
+        return String::new();
+    }
+    let mut summary = format!(
+        " | {} in, {} out",
+        format_tokens(usage.total_input()),
+        format_tokens(usage.output_tokens)
+    );
+    if let Some(cost) = app.conversation_cost(conv) {
+        summary.push_str(&format!(" ≈ ${:.3}", cost));
+    }
+    let session = app.session_usage.total_input() + app.session_usage.output_tokens;
+    if session > 0 {
+        summary.push_str(&format!(" (session {} tokens", format_tokens(session)));
+        if app.session_cost > 0.0 {
+            summary.push_str(&format!(", ${:.3}", app.session_cost));
+        }
+        summary.push(')');
+    }
+    summary
+}
+
+/// The tool the active conversation is waiting on, if any.
+fn running_tool(app: &App) -> Option<&str> {
+    let last = app.current_conversation().messages.last()?;
+    last.pending_tool_calls()
+        .next()
+        .map(|call| call.name.as_str())
+}
+
+/// Shortens large token counts: 950, 12.3k, 1.2M.
+fn format_tokens(tokens: u64) -> String {
+    match tokens {
+        0..=999 => tokens.to_string(),
+        1_000..=999_999 => format!("{:.1}k", tokens as f64 / 1_000.0),
+        _ => format!("{:.1}M", tokens as f64 / 1_000_000.0),
+    }
+}
+
+fn render_help_overlay(config: &Config, frame: &mut Frame, area: Rect) {
+    let keys = &config.keys;
+    let binding =
+        |keys: String, description: &str| Line::from(format!("  {:<15}{}", keys, description));
     let help_text = vec![
         Line::from(""),
-        Line::from(Span::styled("  Normal Mode", Style::default().add_modifier(Modifier::BOLD))),
+        Line::from(Span::styled(
+            "  Normal Mode",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
         Line::from("  ───────────"),
-        Line::from("  i, Enter       Insert mode"),
-        Line::from("  q              Quit"),
-        Line::from("  j, k, ↑, ↓     Scroll messages"),
-        Line::from("  g, G           Top/bottom of chat"),
-        Line::from("  Ctrl+n         New conversation"),
-        Line::from("  Ctrl+w         Close conversation"),
-        Line::from("  Ctrl+h/l       Previous/next tab"),
-        Line::from("  Ctrl+s         Save conversation"),
-        Line::from("  ?              Toggle this help"),
+        binding(format!("{}, Enter", keys.insert), "Insert mode"),
+        binding(keys.quit.to_string(), "Quit"),
+        binding(
+            format!("{}, {}, ↑, ↓", keys.scroll_down, keys.scroll_up),
+            "Scroll one line",
+        ),
+        binding(
+            format!("{}/{}", keys.half_page_down, keys.half_page_up),
+            "Half page down/up",
+        ),
+        binding(
+            format!("{}/{}", keys.page_down, keys.page_up),
+            "Page down/up (PgDn/PgUp)",
+        ),
+        binding(
+            format!("{}, {}", keys.top, keys.bottom),
+            "Top/bottom of chat",
+        ),
+        binding(keys.new_conversation.to_string(), "New conversation"),
+        binding(keys.close_conversation.to_string(), "Close conversation"),
+        binding(
+            format!("{}/{}", keys.prev_tab, keys.next_tab),
+            "Previous/next tab",
+        ),
+        binding(keys.save.to_string(), "Save conversation"),
+        binding(keys.browse.to_string(), "Browse saved conversations"),
+        binding(format!("{}, Esc", keys.cancel), "Cancel response"),
+        binding(keys.toggle_raw_markdown.to_string(), "Toggle raw markdown"),
+        binding(keys.toggle_thinking.to_string(), "Expand/collapse thinking"),
+        binding(keys.edit_input.to_string(), "Compose in $EDITOR"),
+        binding(
+            keys.edit_system_prompt.to_string(),
+            "Edit system prompt in $EDITOR",
+        ),
+        binding(keys.help.to_string(), "Toggle this help"),
         Line::from(""),
-        Line::from(Span::styled("  Insert Mode", Style::default().add_modifier(Modifier::BOLD))),
+        Line::from(Span::styled(
+            "  Insert Mode",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
         Line::from("  ───────────"),
         Line::from("  Escape         Normal mode"),
         Line::from("  Enter          Send message"),
-        Line::from("  ←/→            Move cursor"),
+        Line::from("  Alt+Enter      New line (or Shift+Enter)"),
+        Line::from("  ←/→/↑/↓        Move cursor"),
+        Line::from("  Ctrl+←/→       Move by word"),
+        Line::from("  Home/End       Start/end of line"),
         Line::from("  Backspace      Delete character"),
+        Line::from("  Ctrl+w         Delete word"),
+        Line::from("  Ctrl+g         Compose in $EDITOR"),
         Line::from(""),
-        Line::from(Span::styled("  Commands", Style::default().add_modifier(Modifier::BOLD))),
+        Line::from(Span::styled(
+            "  Commands",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
         Line::from("  ────────"),
         Line::from("  /model         Show current model"),
         Line::from("  /model <name>  Switch model"),
+        Line::from("  /system        Show system prompt"),
+        Line::from("  /system <text> Set system prompt"),
+        Line::from("  /system clear  Remove system prompt"),
+        Line::from("  /system edit   Edit system prompt in $EDITOR"),
+        Line::from("  /continue      Resume a truncated reply"),
+        Line::from("  /attach <path> Attach an image (or write @path)"),
+        Line::from("  /attach clear  Remove attachments"),
+        Line::from("  /think [n|off] Toggle extended thinking"),
+        Line::from("  /tools         List tools the model can use"),
+        Line::from("  /tools dry-run Toggle running tool calls"),
+        Line::from("  /mcp           Inspect MCP servers"),
+        Line::from("  /mcp restart   Restart all MCP servers, or one by name"),
+        Line::from("  /open          Browse saved conversations"),
+        Line::from("  /config        Show config file path"),
+        Line::from("  /config reload Reload config file"),
         Line::from("  /help          Show this help"),
         Line::from(""),
-        Line::from(Span::styled("        Press any key to close", Style::default().fg(Color::DarkGray))),
+        Line::from(Span::styled(
+            "        Press any key to close",
+            Style::default().fg(config.theme.muted),
+        )),
         Line::from(""),
     ];
 
     let help_height = help_text.len() as u16 + 2;
-    let help_width = 50;
+    let help_width = help_text.iter().map(Line::width).max().unwrap_or(0).max(48) as u16 + 2;
 
     let popup_area = centered_rect(help_width, help_height, area);
 
@@ -146,7 +402,7 @@ fn render_help_overlay(frame: &mut Frame, area: Rect) {
         .title(" Keybindings ")
         .borders(Borders::ALL)
         .border_type(ratatui::widgets::BorderType::Rounded)
-        .border_style(Style::default().fg(Color::Cyan));
+        .border_style(Style::default().fg(config.theme.accent));
 
     let help = Paragraph::new(help_text).block(block);
 
@@ -154,6 +410,363 @@ fn render_help_overlay(frame: &mut Frame, area: Rect) {
     frame.render_widget(help, popup_area);
 }
 
+fn render_browser_overlay(browser: &Browser, theme: &Theme, frame: &mut Frame, area: Rect) {
+    let popup_width = (area.width as f32 * 0.8) as u16;
+    let popup_height = (area.height as f32 * 0.7) as u16;
+    let popup_area = centered_rect(popup_width, popup_height, area);
+
+    let title = format!(" Conversations (sort: {}) ", browser.sort.label());
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_type(ratatui::widgets::BorderType::Rounded)
+        .border_style(Style::default().fg(theme.accent));
+
+    let inner = block.inner(popup_area);
+    frame.render_widget(Clear, popup_area);
+    frame.render_widget(block, popup_area);
+
+    let [prompt_area, body_area] =
+        Layout::vertical([Constraint::Length(1), Constraint::Min(1)]).areas(inner);
+    let [list_area, preview_area] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(body_area);
+
+    // Prompt line: filter, rename or delete confirmation
+    let prompt = match &browser.input {
+        BrowseInput::Rename(title) => Line::from(vec![
+            Span::styled("Rename: ", Style::default().fg(theme.prompt)),
+            Span::raw(title.clone()),
+        ]),
+        BrowseInput::ConfirmDelete => Line::from(Span::styled(
+            "Delete this conversation? (y/n)",
+            Style::default().fg(theme.error),
+        )),
+        BrowseInput::Filter => Line::from(vec![
+            Span::styled("Filter: ", Style::default().fg(theme.prompt)),
+            Span::raw(browser.filter.clone()),
+        ]),
+        BrowseInput::None if !browser.filter.is_empty() => Line::from(vec![
+            Span::styled("Filter: ", Style::default().fg(theme.muted)),
+            Span::raw(browser.filter.clone()),
+        ]),
+        BrowseInput::None => Line::from(Span::styled(
+            "/ filter  s sort  r rename  d delete",
+            Style::default().fg(theme.muted),
+        )),
+    };
+    frame.render_widget(Paragraph::new(prompt), prompt_area);
+
+    let visible = browser.visible();
+
+    // Keep the selection in view when the list is taller than the area
+    let rows = list_area.height as usize;
+    let skip = browser.selected.saturating_sub(rows.saturating_sub(1));
+
+    let lines: Vec<Line> = visible
+        .iter()
+        .enumerate()
+        .skip(skip)
+        .take(rows)
+        .map(|(i, entry)| {
+            let meta = format!(
+                "{:>4} msgs  {}",
+                entry.message_count,
+                entry.updated.format("%Y-%m-%d %H:%M")
+            );
+            let title_width =
+                (list_area.width as usize).saturating_sub(text::display_width(&meta) + 3);
+            let title = text::truncate_to_width(&entry.title, title_width);
+            let padding = title_width.saturating_sub(text::display_width(title));
+            let style = if i == browser.selected {
+                Style::default()
+                    .fg(theme.accent)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            let marker = if i == browser.selected { "> " } else { "  " };
+            Line::from(vec![
+                Span::styled(
+                    format!("{}{}{} ", marker, title, " ".repeat(padding)),
+                    style,
+                ),
+                Span::styled(meta, Style::default().fg(theme.muted)),
+            ])
+        })
+        .collect();
+
+    if lines.is_empty() {
+        let empty = Paragraph::new("No matches").style(Style::default().fg(theme.muted));
+        frame.render_widget(empty, list_area);
+    } else {
+        frame.render_widget(Paragraph::new(lines), list_area);
+    }
+
+    // Preview of the first exchange
+    let mut preview: Vec<Line> = Vec::new();
+    if let Some(entry) = visible.get(browser.selected) {
+        for (role, content) in &entry.preview {
+            let (label, color) = match role {
+                Role::User => ("You", theme.user),
+                Role::Assistant => ("Claude", theme.assistant),
+            };
+            preview.push(Line::from(Span::styled(
+                label,
+                Style::default().fg(color).add_modifier(Modifier::BOLD),
+            )));
+            preview.extend(content.lines().map(|l| Line::from(l.to_string())));
+            preview.push(Line::from(""));
+        }
+    }
+
+    let preview_block = Block::default()
+        .borders(Borders::LEFT)
+        .border_style(Style::default().fg(theme.muted));
+    let preview = Paragraph::new(preview)
+        .block(preview_block)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(preview, preview_area);
+}
+
+/// Asks whether the oldest pending tool call may run.
+fn render_approval_overlay(app: &App, frame: &mut Frame, area: Rect) {
+    let Some(approval) = app.approvals.front() else {
+        return;
+    };
+    let theme = &app.config.theme;
+    let call = &approval.call;
+    let popup_width = (area.width as f32 * 0.7) as u16;
+    let text_width = popup_width.saturating_sub(4) as usize;
+    let max_height = (area.height as f32 * 0.8) as u16;
+
+    let mut lines = Vec::new();
+    if let Some(conv) = app.conversation(approval.conversation_id) {
+        lines.push(Line::from(Span::styled(
+            format!("Requested in \"{}\"", conv.display_title()),
+            Style::default().fg(theme.muted),
+        )));
+        lines.push(Line::from(""));
+    }
+    lines.push(Line::from(Span::styled(
+        format!("⚙ {}", call.name),
+        Style::default().add_modifier(Modifier::BOLD),
+    )));
+
+    // A lone command is shown as it would be typed
+    let input = match call.input.as_object() {
+        Some(fields) if fields.len() == 1 && fields.contains_key("command") => {
+            match fields["command"].as_str() {
+                Some(command) => format!("$ {}", command),
+                None => call.input.to_string(),
+            }
+        }
+        _ => serde_json::to_string_pretty(&call.input).unwrap_or_default(),
+    };
+    // Room for the lines around the input, the footer and the borders
+    let input_rows = (max_height as usize).saturating_sub(lines.len() + 4).max(1);
+    let input_lines = hard_wrap(&input, text_width);
+    let hidden = input_lines.len().saturating_sub(input_rows);
+    for line in input_lines.into_iter().take(input_rows) {
+        lines.push(Line::from(Span::styled(
+            line,
+            Style::default().fg(theme.prompt),
+        )));
+    }
+    if hidden > 0 {
+        lines.push(Line::from(Span::styled(
+            format!("… {} more lines", hidden),
+            Style::default().fg(theme.muted),
+        )));
+    }
+
+    let key = |k: &'static str| {
+        Span::styled(
+            k,
+            Style::default()
+                .fg(theme.accent)
+                .add_modifier(Modifier::BOLD),
+        )
+    };
+    lines.push(Line::from(""));
+    lines.push(Line::from(vec![
+        key("y"),
+        Span::raw(" allow once   "),
+        key("Y"),
+        Span::raw(" always in this conversation   "),
+        key("n"),
+        Span::raw(" deny"),
+    ]));
+
+    let title = match app.approvals.len() {
+        1 => " Allow tool call? ".to_string(),
+        n => format!(" Allow tool call? (1 of {}) ", n),
+    };
+    let popup_height = (lines.len() as u16 + 2).min(max_height);
+    let popup_area = centered_rect(popup_width, popup_height, area);
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_type(ratatui::widgets::BorderType::Rounded)
+        .border_style(Style::default().fg(theme.prompt))
+        .padding(Padding::horizontal(1));
+
+    frame.render_widget(Clear, popup_area);
+    frame.render_widget(Paragraph::new(lines).block(block), popup_area);
+}
+
+/// Configured MCP servers on the left; what the selected one offers on the
+/// right.
+fn render_mcp_overlay(app: &App, frame: &mut Frame, area: Rect) {
+    let theme = &app.config.theme;
+    let popup_width = (area.width as f32 * 0.8) as u16;
+    let popup_height = (area.height as f32 * 0.7) as u16;
+    let popup_area = centered_rect(popup_width, popup_height, area);
+
+    let block = Block::default()
+        .title(" MCP servers ")
+        .borders(Borders::ALL)
+        .border_type(ratatui::widgets::BorderType::Rounded)
+        .border_style(Style::default().fg(theme.accent));
+    let inner = block.inner(popup_area);
+    frame.render_widget(Clear, popup_area);
+    frame.render_widget(block, popup_area);
+
+    let [prompt_area, body_area] =
+        Layout::vertical([Constraint::Length(1), Constraint::Min(1)]).areas(inner);
+    let [list_area, details_area] =
+        Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)])
+            .areas(body_area);
+
+    frame.render_widget(
+        Paragraph::new(Span::styled(
+            "j/k select  r restart",
+            Style::default().fg(theme.muted),
+        )),
+        prompt_area,
+    );
+
+    let rows = list_area.height as usize;
+    let skip = app.mcp_selected.saturating_sub(rows.saturating_sub(1));
+    let lines: Vec<Line> = app
+        .mcp_servers
+        .iter()
+        .enumerate()
+        .skip(skip)
+        .take(rows)
+        .map(|(i, server)| {
+            let (symbol, color) = match server.status {
+                McpStatus::Starting => ("○", theme.muted),
+                McpStatus::Running(_) => ("●", theme.assistant),
+                McpStatus::Failed(_) => ("✗", theme.error),
+            };
+            let style = if i == app.mcp_selected {
+                Style::default()
+                    .fg(theme.accent)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            let marker = if i == app.mcp_selected { "> " } else { "  " };
+            Line::from(vec![
+                Span::styled(marker, style),
+                Span::styled(format!("{} ", symbol), Style::default().fg(color)),
+                Span::styled(server.name.clone(), style),
+            ])
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), list_area);
+
+    let details = app
+        .mcp_servers
+        .get(app.mcp_selected)
+        .map(|server| mcp_details(server, theme))
+        .unwrap_or_default();
+    let details_block = Block::default()
+        .borders(Borders::LEFT)
+        .border_style(Style::default().fg(theme.muted))
+        .padding(Padding::horizontal(1));
+    let details = Paragraph::new(details)
+        .block(details_block)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(details, details_area);
+}
+
+fn mcp_details(server: &McpServer, theme: &Theme) -> Vec<Line<'static>> {
+    let heading = |text: String| {
+        Line::from(Span::styled(
+            text,
+            Style::default().add_modifier(Modifier::BOLD),
+        ))
+    };
+    let muted = Style::default().fg(theme.muted);
+    let mut command = server.config.command.clone();
+    for arg in &server.config.args {
+        command.push(' ');
+        command.push_str(arg);
+    }
+
+    let mut lines = vec![heading(server.name.clone())];
+    lines.push(match &server.status {
+        McpStatus::Starting => Line::from(Span::styled("starting…", muted)),
+        McpStatus::Running(session) => Line::from(Span::styled(
+            match &session.server_info {
+                Some(info) => format!("running · {}", info),
+                None => "running".to_string(),
+            },
+            Style::default().fg(theme.assistant),
+        )),
+        McpStatus::Failed(e) => Line::from(Span::styled(
+            format!("failed: {}", e),
+            Style::default().fg(theme.error),
+        )),
+    });
+    lines.push(Line::from(Span::styled(format!("$ {}", command), muted)));
+
+    let Some(session) = server.session() else {
+        return lines;
+    };
+    // Only the first line of a description fits
+    let described = |name: &str, description: Option<&str>| {
+        let mut spans = vec![Span::raw(format!("  {}", name))];
+        if let Some(line) = description
+            .and_then(|d| d.lines().next())
+            .filter(|l| !l.is_empty())
+        {
+            spans.push(Span::styled(format!("  {}", line), muted));
+        }
+        Line::from(spans)
+    };
+
+    lines.push(Line::from(""));
+    lines.push(heading(format!("Tools ({})", session.tools.len())));
+    for tool in &session.tools {
+        lines.push(described(&tool.name, tool.description.as_deref()));
+    }
+    lines.push(Line::from(""));
+    lines.push(heading(format!("Resources ({})", session.resources.len())));
+    for resource in &session.resources {
+        lines.push(described(&resource.name, Some(&resource.uri)));
+    }
+    lines.push(Line::from(""));
+    lines.push(heading(format!("Prompts ({})", session.prompts.len())));
+    for prompt in &session.prompts {
+        lines.push(described(&prompt.name, prompt.description.as_deref()));
+    }
+
+    let stderr = session.stderr.lock().unwrap_or_else(|e| e.into_inner());
+    if !stderr.is_empty() {
+        lines.push(Line::from(""));
+        lines.push(heading("Log".to_string()));
+        lines.extend(
+            stderr
+                .iter()
+                .map(|line| Line::from(Span::styled(line.clone(), muted))),
+        );
+    }
+    lines
+}
+
 fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
     let [area] = Layout::horizontal([Constraint::Length(width)])
         .flex(Flex::Center)
@@ -170,13 +783,19 @@ fn render_tabs(app: &App, frame: &mut Frame, area: Rect) {
         .iter()
         .enumerate()
         .map(|(i, conv)| {
-            let title = conv.display_title();
+            let mut title = conv.display_title().to_string();
+            if conv.system_prompt.is_some() {
+                title = format!("⚙ {}", title);
+            }
+            if conv.is_loading {
+                title.push_str(" …");
+            }
             let style = if i == app.active_tab {
                 Style::default()
-                    .fg(Color::Cyan)
+                    .fg(app.config.theme.accent)
                     .add_modifier(Modifier::BOLD)
             } else {
-                Style::default().fg(Color::DarkGray)
+                Style::default().fg(app.config.theme.muted)
             };
             Line::from(Span::styled(title, style))
         })
@@ -189,122 +808,450 @@ fn render_tabs(app: &App, frame: &mut Frame, area: Rect) {
     frame.render_widget(tabs, area);
 }
 
-fn render_messages(app: &App, frame: &mut Frame, area: Rect) {
-    let conversation = app.current_conversation();
+/// Rendered bubble contents for one conversation, reused across frames so
+/// markdown and highlighting only run for messages that changed.
+#[derive(Default)]
+pub struct LayoutCache {
+    width: usize,
+    raw_markdown: bool,
+    show_thinking: bool,
+    bubbles: Vec<CachedBubble>,
+}
+
+/// Identifies a message revision. Content and thinking only change length
+/// while streaming or continuing, and tool calls only gain results.
+#[derive(PartialEq)]
+struct BubbleKey {
+    timestamp: DateTime<Local>,
+    content_len: usize,
+    thinking_len: usize,
+    tool_calls: usize,
+    tool_results: usize,
+    truncated: bool,
+    interrupted: bool,
+}
+
+impl BubbleKey {
+    fn new(message: &Message) -> Self {
+        let thinking_len = message
+            .thinking
+            .iter()
+            .map(|block| match block {
+                ThinkingBlock::Thinking { thinking, .. } => thinking.len() + 1,
+                ThinkingBlock::Redacted { .. } => 1,
+            })
+            .sum();
+        Self {
+            timestamp: message.timestamp,
+            content_len: message.content.len(),
+            thinking_len,
+            tool_calls: message.tool_calls.len(),
+            tool_results: message
+                .tool_calls
+                .iter()
+                .filter(|c| c.result.is_some())
+                .count(),
+            truncated: message.is_truncated(),
+            interrupted: message.interrupted,
+        }
+    }
+}
+
+struct CachedBubble {
+    key: BubbleKey,
+    lines: Vec<Line<'static>>,
+    width: u16,
+}
+
+impl LayoutCache {
+    fn update(
+        &mut self,
+        messages: &[Message],
+        width: usize,
+        raw_markdown: bool,
+        show_thinking: bool,
+    ) {
+        if self.width != width
+            || self.raw_markdown != raw_markdown
+            || self.show_thinking != show_thinking
+        {
+            self.bubbles.clear();
+            self.width = width;
+            self.raw_markdown = raw_markdown;
+            self.show_thinking = show_thinking;
+        }
+        self.bubbles.truncate(messages.len());
+
+        for (i, message) in messages.iter().enumerate() {
+            let key = BubbleKey::new(message);
+            if self.bubbles.get(i).is_some_and(|b| b.key == key) {
+                continue;
+            }
+            let bubble = CachedBubble::new(message, key, width, raw_markdown, show_thinking);
+            if i < self.bubbles.len() {
+                self.bubbles[i] = bubble;
+            } else {
+                self.bubbles.push(bubble);
+            }
+        }
+    }
+}
+
+impl CachedBubble {
+    fn new(
+        message: &Message,
+        key: BubbleKey,
+        width: usize,
+        raw_markdown: bool,
+        show_thinking: bool,
+    ) -> Self {
+        let mut lines = thinking_lines(&message.thinking, width, show_thinking);
+        lines.extend(
+            message
+                .attachments
+                .iter()
+                .map(|a| attachment_chip(a, width)),
+        );
+        if !lines.is_empty() && !message.content.is_empty() {
+            lines.push(Line::from(""));
+        }
+
+        // Assistant replies are rendered as markdown
+        if message.role == Role::Assistant && !raw_markdown {
+            lines.extend(markdown::render(&message.content, width));
+        } else {
+            lines.extend(
+                text::wrap_text(&message.content, width)
+                    .into_iter()
+                    .map(Line::from),
+            );
+        }
+        for call in &message.tool_calls {
+            if !lines.is_empty() {
+                lines.push(Line::from(""));
+            }
+            lines.extend(tool_call_lines(call, width, message.interrupted));
+        }
+        if message.is_truncated() {
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(
+                "⚠ truncated (max_tokens) · /continue to resume",
+                Style::default().add_modifier(Modifier::DIM | Modifier::ITALIC),
+            )));
+        }
+
+        let content_width = lines.iter().map(|l| l.width()).max().unwrap_or(0);
+        let width = content_width.min(width + 2) as u16 + 4; // padding
+
+        Self { key, lines, width }
+    }
+
+    /// Bubble, timestamp row and spacer row.
+    fn height(&self) -> usize {
+        self.lines.len() + 4
+    }
+}
+
+/// Dimmed thinking section shown above a reply: a one-line summary when
+/// collapsed, the full text when expanded.
+fn thinking_lines(blocks: &[ThinkingBlock], width: usize, expanded: bool) -> Vec<Line<'static>> {
+    if blocks.is_empty() {
+        return Vec::new();
+    }
+    let style = Style::default().add_modifier(Modifier::DIM | Modifier::ITALIC);
+
+    if !expanded {
+        let words: usize = blocks
+            .iter()
+            .map(|block| match block {
+                ThinkingBlock::Thinking { thinking, .. } => thinking.split_whitespace().count(),
+                ThinkingBlock::Redacted { .. } => 0,
+            })
+            .sum();
+        let summary = if words > 0 {
+            format!("▸ Thinking ({} words)", words)
+        } else if blocks
+            .iter()
+            .all(|b| matches!(b, ThinkingBlock::Redacted { .. }))
+        {
+            "▸ Thinking (redacted)".to_string()
+        } else {
+            "▸ Thinking…".to_string()
+        };
+        return vec![Line::from(Span::styled(summary, style))];
+    }
+
+    let mut lines = vec![Line::from(Span::styled("▾ Thinking", style))];
+    for block in blocks {
+        match block {
+            ThinkingBlock::Thinking { thinking, .. } => lines.extend(
+                text::wrap_text(thinking.trim(), width)
+                    .into_iter()
+                    .map(|line| Line::from(Span::styled(line, style))),
+            ),
+            ThinkingBlock::Redacted { .. } => {
+                lines.push(Line::from(Span::styled("(redacted)", style)));
+            }
+        }
+    }
+    lines
+}
+
+/// One line naming an attached image and its size.
+fn attachment_chip(attachment: &Attachment, width: usize) -> Line<'static> {
+    let (detail, style) = match attachment.data {
+        Some(_) => (
+            attachment::format_size(attachment.size),
+            Style::default().add_modifier(Modifier::REVERSED),
+        ),
+        None => (
+            "missing".to_string(),
+            Style::default().add_modifier(Modifier::REVERSED | Modifier::DIM),
+        ),
+    };
+    let chip = format!(" 📎 {} · {} ", attachment.file_name(), detail);
+    Line::from(Span::styled(
+        text::truncate_to_width(&chip, width).to_string(),
+        style,
+    ))
+}
+
+/// A tool call and a preview of its result.
+fn tool_call_lines(call: &ToolCall, width: usize, interrupted: bool) -> Vec<Line<'static>> {
+    let dim = Style::default().add_modifier(Modifier::DIM);
+    let name = format!("⚙ {} ", call.name);
+    let input = call.input.to_string();
+    let input_width = width.saturating_sub(text::display_width(&name));
+    let mut input_text = text::truncate_to_width(&input, input_width).to_string();
+    if input_text.len() < input.len() {
+        input_text = format!(
+            "{}…",
+            text::truncate_to_width(&input, input_width.saturating_sub(1))
+        );
+    }
+    let mut lines = vec![Line::from(vec![
+        Span::styled(name, Style::default().add_modifier(Modifier::BOLD)),
+        Span::styled(input_text, dim),
+    ])];
+
+    let Some(result) = &call.result else {
+        let state = if interrupted { "not run" } else { "pending" };
+        lines.push(Line::from(Span::styled(format!("  {}", state), dim)));
+        return lines;
+    };
+    let marker = if result.is_error { "✗ " } else { "↳ " };
+    let content = if result.content.trim().is_empty() {
+        "(no output)"
+    } else {
+        result.content.trim_end()
+    };
+    let total = content.lines().count();
+    let preview: Vec<&str> = content.lines().take(TOOL_PREVIEW_LINES).collect();
+    for (i, line) in hard_wrap(&preview.join("\n"), width.saturating_sub(2))
+        .into_iter()
+        .enumerate()
+    {
+        let prefix = if i == 0 { marker } else { "  " };
+        lines.push(Line::from(Span::styled(format!("{}{}", prefix, line), dim)));
+    }
+    if total > TOOL_PREVIEW_LINES {
+        lines.push(Line::from(Span::styled(
+            format!("  … {} more lines", total - TOOL_PREVIEW_LINES),
+            dim.add_modifier(Modifier::ITALIC),
+        )));
+    }
+    lines
+}
+
+fn render_messages(app: &mut App, frame: &mut Frame, area: Rect) {
+    let show_raw = app.show_raw_markdown;
+    let show_thinking = app.show_thinking;
+    let theme = &app.config.theme;
+    let conversation = &mut app.conversations[app.active_tab];
 
     if conversation.messages.is_empty() {
-        let hint = Paragraph::new("Start typing to begin a conversation.\nPress 'i' to enter insert mode, '?' for help.")
+        conversation.content_height = 0;
+        conversation.scroll_offset = 0;
+        let hint = Paragraph::new(format!(
+            "Start typing to begin a conversation.\nPress '{}' to enter insert mode, '{}' for help.",
+            app.config.keys.insert, app.config.keys.help
+        ))
             .alignment(Alignment::Center)
-            .style(Style::default().fg(Color::DarkGray));
+            .style(Style::default().fg(theme.muted));
         frame.render_widget(hint, area);
         return;
     }
 
-    let max_bubble_width = (area.width as f32 * 0.7) as u16;
-    let mut y_offset = area.y;
+    let max_bubble_width = (area.width as f32 * theme.bubble_width) as u16;
+    let cache = app.layouts.entry(conversation.id).or_default();
+    cache.update(
+        &conversation.messages,
+        max_bubble_width.saturating_sub(4) as usize,
+        show_raw,
+        show_thinking,
+    );
 
-    // Calculate visible messages based on scroll offset
-    let visible_messages = conversation
-        .messages
-        .iter()
-        .skip(conversation.scroll_offset);
+    conversation.content_height = cache.bubbles.iter().map(CachedBubble::height).sum();
+    conversation.viewport_height = area.height as usize;
+    conversation.scroll_offset = if conversation.follow_bottom {
+        conversation.max_scroll()
+    } else {
+        conversation.scroll_offset.min(conversation.max_scroll())
+    };
+
+    // Visible window in content lines
+    let top = conversation.scroll_offset;
+    let bottom = top + area.height as usize;
+    let mut y = 0;
 
-    for message in visible_messages {
-        if y_offset >= area.y + area.height {
+    for (message, bubble) in conversation.messages.iter().zip(&cache.bubbles) {
+        if y >= bottom {
             break;
         }
+        let block_top = y;
+        y += bubble.height();
+        if y <= top {
+            continue;
+        }
 
         let (border_color, alignment) = match message.role {
-            Role::User => (Color::Blue, Alignment::Right),
-            Role::Assistant => (Color::Green, Alignment::Left),
+            Role::User => (theme.user, Alignment::Right),
+            Role::Assistant => (theme.assistant, Alignment::Left),
         };
 
-        // Wrap text for bubble
-        let content_lines = wrap_text(&message.content, max_bubble_width.saturating_sub(4) as usize);
-        let bubble_height = content_lines.len() as u16 + 2; // +2 for borders
-
-        if y_offset + bubble_height + 1 > area.y + area.height {
-            break;
-        }
-
-        // Calculate bubble position
-        let bubble_width = content_lines
-            .iter()
-            .map(|l| l.len())
-            .max()
-            .unwrap_or(0)
-            .min(max_bubble_width as usize - 2) as u16
-            + 4; // padding
-
         let bubble_x = match alignment {
-            Alignment::Right => area.x + area.width - bubble_width - 1,
-            Alignment::Left => area.x + 1,
-            _ => area.x,
+            Alignment::Right => (area.x + area.width).saturating_sub(bubble.width + 1),
+            _ => area.x + 1,
         };
 
-        let bubble_rect = Rect::new(bubble_x, y_offset, bubble_width, bubble_height);
+        // Bubble rows, clipped to the window. Borders that scroll out of view
+        // are dropped so a partially visible bubble stays open at that edge.
+        let bubble_bottom = block_top + bubble.lines.len() + 2;
+        let visible_start = block_top.max(top);
+        let visible_end = bubble_bottom.min(bottom);
+
+        if visible_start < visible_end {
+            let mut borders = Borders::LEFT | Borders::RIGHT;
+            if block_top >= top {
+                borders |= Borders::TOP;
+            }
+            if bubble_bottom <= bottom {
+                borders |= Borders::BOTTOM;
+            }
 
-        let block = Block::default()
-            .borders(Borders::ALL)
-            .border_type(ratatui::widgets::BorderType::Rounded)
-            .border_style(Style::default().fg(border_color));
+            let first_line = visible_start.saturating_sub(block_top + 1);
+            let rows = visible_end - visible_start;
+            let lines: Vec<Line> = bubble
+                .lines
+                .iter()
+                .skip(first_line)
+                .take(rows)
+                .cloned()
+                .collect();
 
-        let text_lines: Vec<Line> = content_lines.into_iter().map(Line::from).collect();
-        let paragraph = Paragraph::new(text_lines).block(block).wrap(Wrap { trim: false });
+            let block = Block::default()
+                .borders(borders)
+                .border_type(ratatui::widgets::BorderType::Rounded)
+                .border_style(Style::default().fg(border_color));
 
-        frame.render_widget(paragraph, bubble_rect);
+            let bubble_rect = Rect::new(
+                bubble_x,
+                area.y + (visible_start - top) as u16,
+                bubble.width,
+                rows as u16,
+            );
+            frame.render_widget(Paragraph::new(lines).block(block), bubble_rect);
+        }
 
         // Timestamp below bubble
-        let timestamp = message.timestamp.format("%H:%M").to_string();
-        let timestamp_x = match alignment {
-            Alignment::Right => bubble_x + bubble_width - timestamp.len() as u16,
-            _ => bubble_x,
-        };
+        let timestamp_row = bubble_bottom;
+        if timestamp_row >= top && timestamp_row < bottom {
+            let mut timestamp = message.timestamp.format("%H:%M").to_string();
+            if let Some(usage) = message.usage {
+                timestamp.push_str(&format!(
+                    " · {} in, {} out",
+                    format_tokens(usage.total_input()),
+                    format_tokens(usage.output_tokens)
+                ));
+            }
+            if message.interrupted {
+                timestamp.push_str(" · interrupted");
+            }
+            let timestamp_x = match alignment {
+                Alignment::Right => {
+                    (bubble_x + bubble.width).saturating_sub(text::display_width(&timestamp) as u16)
+                }
+                _ => bubble_x,
+            };
 
-        let timestamp_span = Span::styled(timestamp, Style::default().fg(Color::DarkGray).dim());
-        let timestamp_rect = Rect::new(timestamp_x, y_offset + bubble_height, timestamp_span.width() as u16, 1);
-        frame.render_widget(Paragraph::new(Line::from(timestamp_span)), timestamp_rect);
+            let timestamp_span = Span::styled(timestamp, Style::default().fg(theme.muted).dim());
+            let timestamp_rect = Rect::new(
+                timestamp_x,
+                area.y + (timestamp_row - top) as u16,
+                timestamp_span.width() as u16,
+                1,
+            );
+            frame.render_widget(Paragraph::new(Line::from(timestamp_span)), timestamp_rect);
+        }
+    }
 
-        y_offset += bubble_height + 2; // bubble + timestamp + spacing
+    if conversation.content_height > conversation.viewport_height {
+        let mut state =
+            ScrollbarState::new(conversation.max_scroll()).position(conversation.scroll_offset);
+        let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
+            .begin_symbol(None)
+            .end_symbol(None)
+            .style(Style::default().fg(theme.muted));
+        frame.render_stateful_widget(scrollbar, area, &mut state);
     }
 }
 
-fn wrap_text(text: &str, max_width: usize) -> Vec<String> {
-    let mut lines = Vec::new();
-    for line in text.lines() {
-        if line.is_empty() {
-            lines.push(String::new());
-            continue;
-        }
+/// Splits the input into display rows of at most `width` columns, breaking
+/// at newlines and wherever a line overflows. Returns the rows and the
+/// cursor's row and column.
+fn layout_input(input: &str, cursor: usize, width: usize) -> (Vec<&str>, (usize, usize)) {
+    let width = width.max(1);
+    let mut rows = Vec::new();
+    let mut cursor_pos = (0, 0);
+    let mut line_start = 0;
 
-        let mut current_line = String::new();
-        for word in line.split_whitespace() {
-            if current_line.is_empty() {
-                if word.len() > max_width {
-                    // Word is too long, split it
-                    let mut remaining = word;
-                    while remaining.len() > max_width {
-                        lines.push(remaining[..max_width].to_string());
-                        remaining = &remaining[max_width..];
-                    }
-                    current_line = remaining.to_string();
-                } else {
-                    current_line = word.to_string();
-                }
-            } else if current_line.len() + 1 + word.len() <= max_width {
-                current_line.push(' ');
-                current_line.push_str(word);
-            } else {
-                lines.push(current_line);
-                current_line = word.to_string();
+    for line in input.split('\n') {
+        let mut remaining = line;
+        let mut row_start = line_start;
+        loop {
+            let (row, rest) = text::split_at_width(remaining, width);
+            let row_end = row_start + row.len();
+            // The cursor belongs to this row unless it sits at a soft break,
+            // in which case it is shown at the start of the next row
+            if cursor >= row_start && (cursor < row_end || (cursor == row_end && rest.is_empty())) {
+                cursor_pos = (rows.len(), text::display_width(&input[row_start..cursor]));
             }
+            rows.push(row);
+            if rest.is_empty() {
+                break;
+            }
+            remaining = rest;
+            row_start = row_end;
         }
-        if !current_line.is_empty() {
-            lines.push(current_line);
-        }
+        line_start += line.len() + 1;
     }
-    if lines.is_empty() {
-        lines.push(String::new());
+
+    (rows, cursor_pos)
+}
+
+/// Breaks lines at exactly `max_width` columns, keeping whitespace, for
+/// output where indentation matters.
+fn hard_wrap(text: &str, max_width: usize) -> Vec<String> {
+    let max_width = max_width.max(1);
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let mut remaining = line;
+        while text::display_width(remaining) > max_width {
+            let (head, tail) = text::split_at_width(remaining, max_width);
+            lines.push(head.to_string());
+            remaining = tail;
+        }
+        lines.push(remaining.to_string());
     }
     lines
 }
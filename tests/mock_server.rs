@@ -0,0 +1,297 @@
+//! Streams replies from `mock_server` through the real client.
+
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use std::time::Duration;
+
+use claude_tui::api::{ApiClient, ApiError, StreamChunk};
+use claude_tui::config::{Config, RetryConfig};
+use claude_tui::conversation::{Message, Role, ThinkingBlock};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+/// A running `mock_server`, killed when dropped.
+struct MockServer {
+    child: Child,
+    script: PathBuf,
+    url: String,
+}
+
+impl MockServer {
+    fn start(script: Value) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "claude-tui-script-{}-{}.json",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, script.to_string()).unwrap();
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_mock_server"))
+            .arg(&path)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let url = line
+            .trim()
+            .strip_prefix("listening on ")
+            .unwrap_or_else(|| panic!("unexpected mock_server output: {:?}", line))
+            .to_string();
+        Self {
+            child,
+            script: path,
+            url,
+        }
+    }
+
+    fn client(&self) -> ApiClient {
+        static ENV: Once = Once::new();
+        ENV.call_once(|| {
+            std::env::set_var("ANTHROPIC_API_KEY", "test");
+            std::env::remove_var("ANTHROPIC_BASE_URL");
+        });
+        let config = Config {
+            api_base_url: Some(self.url.clone()),
+            retry: RetryConfig {
+                base_delay_ms: 10,
+                ..RetryConfig::default()
+            },
+            ..Config::default()
+        };
+        ApiClient::new(&config).unwrap()
+    }
+
+    /// Sends one user message without streaming.
+    async fn send(&self) -> Result<Message, ApiError> {
+        let messages = [Message::new(Role::User, "Hi".to_string())];
+        self.client()
+            .send_message(&messages, None, None, None, &[])
+            .await
+    }
+
+    /// Sends one user message and collects everything the stream reports.
+    async fn stream(&self) -> (Vec<StreamChunk>, Result<(), ApiError>) {
+        let client = self.client();
+        let messages = [Message::new(Role::User, "Hi".to_string())];
+        let (tx, mut rx) = mpsc::channel(32);
+        let send = client.send_message_streaming(&messages, None, None, None, &[], tx);
+        let collect = async {
+            let mut chunks = Vec::new();
+            while let Some(chunk) = rx.recv().await {
+                chunks.push(chunk);
+            }
+            chunks
+        };
+        let (result, chunks) = tokio::join!(send, collect);
+        (chunks, result)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.script);
+    }
+}
+
+fn text(chunks: &[StreamChunk]) -> String {
+    chunks
+        .iter()
+        .filter_map(|chunk| match chunk {
+            StreamChunk::Text(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn stop_reason(chunks: &[StreamChunk]) -> Option<&str> {
+    chunks.iter().find_map(|chunk| match chunk {
+        StreamChunk::Stop(reason) => Some(reason.as_str()),
+        _ => None,
+    })
+}
+
+fn ends_with_done(chunks: &[StreamChunk]) -> bool {
+    matches!(chunks.last(), Some(StreamChunk::Done))
+}
+
+#[tokio::test]
+async fn streams_text_in_chunks() {
+    let server = MockServer::start(
+        json!([{ "text": "Hello, world!", "chunk_size": 4, "chunk_delay_ms": 0 }]),
+    );
+    let (chunks, result) = server.stream().await;
+
+    assert!(result.is_ok());
+    assert_eq!(text(&chunks), "Hello, world!");
+    let deltas = chunks
+        .iter()
+        .filter(|c| matches!(c, StreamChunk::Text(_)))
+        .count();
+    assert_eq!(deltas, 4);
+    assert!(chunks
+        .iter()
+        .any(|c| matches!(c, StreamChunk::Usage(u) if u.output_tokens > 0)));
+    assert_eq!(stop_reason(&chunks), Some("end_turn"));
+    assert!(ends_with_done(&chunks));
+}
+
+#[tokio::test]
+async fn retries_overloaded_after_the_requested_delay() {
+    let server = MockServer::start(json!([
+        { "status": 529, "headers": { "retry-after": "1" } },
+        { "text": "Back again", "chunk_delay_ms": 0 },
+    ]));
+    let (chunks, result) = server.stream().await;
+
+    assert!(result.is_ok());
+    match chunks.first() {
+        Some(StreamChunk::Retry(retry)) => {
+            assert_eq!(retry.attempt, 1);
+            assert_eq!(retry.delay, Duration::from_secs(1));
+        }
+        _ => panic!("expected a retry first"),
+    }
+    assert_eq!(text(&chunks), "Back again");
+    assert!(ends_with_done(&chunks));
+}
+
+#[tokio::test]
+async fn reports_an_error_in_the_middle_of_the_stream() {
+    let server = MockServer::start(json!([{ "events": [
+        { "event": "message_start", "data": { "type": "message_start", "message": {
+            "id": "msg_mock", "type": "message", "role": "assistant", "model": "mock", "content": [],
+            "stop_reason": null, "stop_sequence": null, "usage": { "input_tokens": 5, "output_tokens": 1 } } } },
+        { "event": "content_block_start", "data": { "type": "content_block_start", "index": 0,
+            "content_block": { "type": "text", "text": "" } } },
+        { "event": "content_block_delta", "data": { "type": "content_block_delta", "index": 0,
+            "delta": { "type": "text_delta", "text": "Partial" } } },
+        { "event": "error", "data": { "type": "error",
+            "error": { "type": "overloaded_error", "message": "Overloaded" } } },
+    ] }]));
+    let (chunks, _) = server.stream().await;
+
+    assert_eq!(text(&chunks), "Partial");
+    assert!(matches!(
+        chunks.last(),
+        Some(StreamChunk::Error(ApiError::Overloaded(message))) if message == "Overloaded"
+    ));
+    assert!(!chunks.iter().any(|c| matches!(c, StreamChunk::Done)));
+}
+
+#[tokio::test]
+async fn streams_thinking_before_the_text() {
+    let server = MockServer::start(
+        json!([{ "thinking": "Let me see...", "text": "42", "chunk_delay_ms": 0 }]),
+    );
+    let (chunks, result) = server.stream().await;
+
+    assert!(result.is_ok());
+    let start = chunks
+        .iter()
+        .position(|c| {
+            matches!(
+                c,
+                StreamChunk::ThinkingStart(ThinkingBlock::Thinking { .. })
+            )
+        })
+        .expect("a thinking block");
+    let first_text = chunks
+        .iter()
+        .position(|c| matches!(c, StreamChunk::Text(_)))
+        .unwrap();
+    assert!(start < first_text);
+    let thinking: String = chunks
+        .iter()
+        .filter_map(|c| match c {
+            StreamChunk::Thinking(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(thinking, "Let me see...");
+    assert!(chunks
+        .iter()
+        .any(|c| matches!(c, StreamChunk::Signature(s) if !s.is_empty())));
+    assert_eq!(text(&chunks), "42");
+}
+
+#[tokio::test]
+async fn assembles_tool_calls_from_their_streamed_input() {
+    let input = json!({ "path": "src/main.rs", "limit": 20 });
+    let server = MockServer::start(json!([{
+        "text": "Reading it.",
+        "tool_use": [{ "name": "read_file", "input": input }],
+        "stop_reason": "tool_use",
+        "chunk_delay_ms": 0,
+    }]));
+    let (chunks, result) = server.stream().await;
+
+    assert!(result.is_ok());
+    let calls: Vec<_> = chunks
+        .iter()
+        .filter_map(|c| match c {
+            StreamChunk::ToolUse(call) => Some(call),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].name, "read_file");
+    assert_eq!(calls[0].input, input);
+    assert!(!calls[0].id.is_empty());
+    assert_eq!(stop_reason(&chunks), Some("tool_use"));
+    assert!(ends_with_done(&chunks));
+}
+
+#[tokio::test]
+async fn parses_every_block_of_a_non_streaming_reply() {
+    let input = json!({ "pattern": "TODO" });
+    let server = MockServer::start(json!([{
+        "thinking": "Search first.",
+        "text": "Looking.",
+        "tool_use": [{ "name": "grep", "input": input }],
+        "stop_reason": "tool_use",
+    }]));
+    let message = server.send().await.unwrap();
+
+    assert!(message.role == Role::Assistant);
+    assert_eq!(message.content, "Looking.");
+    assert!(matches!(
+        message.thinking.as_slice(),
+        [ThinkingBlock::Thinking { thinking, signature }] if thinking == "Search first." && !signature.is_empty()
+    ));
+    assert_eq!(message.tool_calls.len(), 1);
+    assert_eq!(message.tool_calls[0].name, "grep");
+    assert_eq!(message.tool_calls[0].input, input);
+    assert!(message.tool_calls[0].result.is_none());
+    assert_eq!(message.stop_reason.as_deref(), Some("tool_use"));
+    assert!(message.usage.is_some_and(|u| u.output_tokens > 0));
+}
+
+#[tokio::test]
+async fn retries_a_non_streaming_request() {
+    let server = MockServer::start(json!([
+        { "status": 529, "headers": { "retry-after": "0" } },
+        { "text": "Second try" },
+    ]));
+    let message = server.send().await.unwrap();
+
+    assert_eq!(message.content, "Second try");
+}
+
+#[tokio::test]
+async fn reports_a_non_streaming_error() {
+    let server = MockServer::start(json!([{ "status": 401 }]));
+
+    assert!(matches!(
+        server.send().await,
+        Err(ApiError::Authentication(_))
+    ));
+}